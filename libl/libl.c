@@ -3,7 +3,7 @@
 char* yytext = NULL;
 int   yyleng = 0;
 int   yylineno = 1;
-int   yycolumn = 0;
+int   yycolumn = 1;
 FILE* yyin = NULL;
 
 /* Weak default implementations */
@@ -6,6 +6,7 @@
 #include <string.h>
 
 extern int yylex(void);
+extern int yywrap(void);
 
 /* Default global state */
 extern char* yytext;
@@ -1,40 +1,253 @@
 use std::env;
+use std::str::FromStr;
 
+use crate::Backend;
+
+pub const USAGE: &str = "\
+Usage: lex [OPTIONS] [FILE]
+
+Generate a scanner from the lex specification FILE, or from stdin when FILE
+is missing or `-`.
+
+Options:
+  -o, --outfile=FILE      write the scanner to FILE (default lex.yy.c or lex.yy.rs)
+  -t, --stdout            write the scanner to stdout
+      --header-file=FILE  also write a C header declaring the scanner interface
+  -P, --prefix=PREFIX     use PREFIX instead of `yy` for exported names
+  -i, --case-insensitive  letters in patterns match both cases
+      --utf8              patterns match UTF-8 encoded characters, not bytes
+  -d, --debug             scanner reports each matched rule on stderr
+  -v, --verbose           print automaton statistics on stderr
+      --language=LANG     generate `c` (default) or `rust`, which implies --utf8
+      --backend=BACKEND   `switch` (default), `compressed` or `full`
+  -Cf                     same as --backend=full
+  -Ce, -Cm, -Cem          same as --backend=compressed
+      --Werror            treat warnings as errors
+      --dump-nfa          write the NFA as Graphviz to <name>.nfa.dot
+      --dump-dfa          write the DFA as Graphviz to <name>.dfa.dot
+  -h, --help              print this help and exit
+  -V, --version           print the version and exit
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    C,
+    Rust,
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "c" => Ok(Language::C),
+            "rust" => Ok(Language::Rust),
+            _ => Err(format!("Unknown language '{}'", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    // Spec file, `None` reads stdin
+    pub input: Option<String>,
+    pub outfile: Option<String>,
+    pub stdout: bool,
+    pub header_file: Option<String>,
+    pub prefix: Option<String>,
+    pub case_insensitive: bool,
+    pub utf8: bool,
+    pub debug: bool,
+    pub verbose: bool,
+    pub language: Language,
+    pub backend: Backend,
+    pub werror: bool,
+    pub dump_nfa: bool,
+    pub dump_dfa: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            input: None,
+            outfile: None,
+            stdout: false,
+            header_file: None,
+            prefix: None,
+            case_insensitive: false,
+            utf8: false,
+            debug: false,
+            verbose: false,
+            language: Language::default(),
+            backend: Backend::Switch,
+            werror: false,
+            dump_nfa: false,
+            dump_dfa: false,
+            help: false,
+            version: false,
+        }
+    }
+}
+
+impl Args {
+    // Where the scanner goes when neither -o nor -t is given
+    pub fn default_outfile(&self) -> &'static str {
+        match self.language {
+            Language::C => "lex.yy.c",
+            Language::Rust => "lex.yy.rs",
+        }
+    }
+}
+
+// Flex-style command line: short flags can be grouped (`-tv`), values of
+// short options can be attached (`-ofile`) and long options take `=value`
+// or the next argument
 pub struct ArgsParser {
-    args: Vec<String>
+    args: Vec<String>,
+    pos: usize,
+}
+
+impl Default for ArgsParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ArgsParser {
+    // Arguments of the running program, without the program name
     pub fn new() -> Self {
-        let args = env::args().collect();
+        Self::from_args(env::args().skip(1).collect())
+    }
 
-        Self { args }
+    pub fn from_args(args: Vec<String>) -> Self {
+        Self { args, pos: 0 }
     }
 
-    // Parse arugment in a window of size 2 
-    // Format:
-    //   0: option
-    //   1: value
-    pub fn get_argument(&self, name: &str, default: &str) -> String {
-        self.args
-            .windows(2)
-            .find(|window| window[0] == name.to_string())
-            .map(|window| window[1].clone())
-            .unwrap_or_else(|| default.to_string())
+    pub fn parse(mut self) -> Result<Args, String> {
+        let mut args = Args::default();
+        let mut options_done = false;
+        let mut input_seen = false;
+
+        while let Some(arg) = self.next_arg() {
+            if options_done || arg == "-" || !arg.starts_with('-') {
+                if input_seen {
+                    return Err(format!("Unexpected argument '{}', only one input file is supported", arg));
+                }
+                input_seen = true;
+                args.input = Some(arg).filter(|arg| arg != "-");
+            } else if arg == "--" {
+                options_done = true;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                self.parse_long(long, &mut args)?;
+            } else {
+                self.parse_short(&arg[1..], &mut args)?;
+            }
+        }
+
+        if args.language == Language::Rust {
+            let c_only = [
+                ("--header-file", args.header_file.is_some()),
+                ("--prefix", args.prefix.is_some()),
+                ("--debug", args.debug),
+            ];
+            if let Some((option, _)) = c_only.iter().find(|(_, set)| *set) {
+                return Err(format!("Option '{}' is only supported with --language=c", option));
+            }
+            // The generated lexer scans a &str, patterns matching single
+            // bytes would split its chars
+            args.utf8 = true;
+        }
+
+        Ok(args)
     }
 
-    // Check if args contains flag
-    pub fn has_flag(&self, flag: &str) -> bool {
-        self.args.contains(&flag.to_string())
+    fn next_arg(&mut self) -> Option<String> {
+        let arg = self.args.get(self.pos).cloned();
+        self.pos += 1;
+        arg
     }
 
-    // Get the file path (last argument)
-    pub fn get_file(&self) -> String {
-        self.args.last().unwrap().clone()
+    // Value of an option, attached or in the next argument
+    fn value(&mut self, option: &str, attached: Option<String>) -> Result<String, String> {
+        attached
+            .or_else(|| self.next_arg())
+            .ok_or_else(|| format!("Option '{}' requires an argument", option))
+    }
+
+    fn parse_long(&mut self, long: &str, args: &mut Args) -> Result<(), String> {
+        let (name, attached) = match long.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (long, None),
+        };
+        let option = format!("--{}", name);
+
+        match name {
+            "outfile" => args.outfile = Some(self.value(&option, attached)?),
+            "header-file" => args.header_file = Some(self.value(&option, attached)?),
+            "prefix" => args.prefix = Some(self.value(&option, attached)?),
+            "language" => args.language = self.value(&option, attached)?.parse()?,
+            "backend" => args.backend = self.value(&option, attached)?.parse()?,
+            _ => {
+                let flag = match name {
+                    "stdout" => &mut args.stdout,
+                    "case-insensitive" => &mut args.case_insensitive,
+                    "utf8" => &mut args.utf8,
+                    "debug" => &mut args.debug,
+                    "verbose" => &mut args.verbose,
+                    "Werror" => &mut args.werror,
+                    "dump-nfa" => &mut args.dump_nfa,
+                    "dump-dfa" => &mut args.dump_dfa,
+                    "help" => &mut args.help,
+                    "version" => &mut args.version,
+                    _ => return Err(format!("Unknown option '{}'", option)),
+                };
+                if attached.is_some() {
+                    return Err(format!("Option '{}' doesn't take an argument", option));
+                }
+                *flag = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_short(&mut self, group: &str, args: &mut Args) -> Result<(), String> {
+        for (i, c) in group.char_indices() {
+            // The rest of the group is the value, if there is any
+            let rest = &group[i + c.len_utf8()..];
+            let attached = Some(rest.to_string()).filter(|rest| !rest.is_empty());
+            let option = format!("-{}", c);
+
+            match c {
+                'o' => args.outfile = Some(self.value(&option, attached)?),
+                'P' => args.prefix = Some(self.value(&option, attached)?),
+                'C' => args.backend = Self::table_backend(rest)?,
+                't' => args.stdout = true,
+                'i' => args.case_insensitive = true,
+                'd' => args.debug = true,
+                'v' => args.verbose = true,
+                'h' => args.help = true,
+                'V' => args.version = true,
+                _ => return Err(format!("Unknown option '{}'", option)),
+            }
+
+            if matches!(c, 'o' | 'P' | 'C') {
+                break;
+            }
+        }
+
+        Ok(())
     }
 
-    // Get reference to args
-    pub fn args(&self) -> &Vec<String> {
-        &self.args
+    // Table compression flags of flex's -C option
+    fn table_backend(flags: &str) -> Result<Backend, String> {
+        match flags {
+            "f" | "F" => Ok(Backend::Full),
+            "" | "e" | "m" | "em" | "me" => Ok(Backend::Compressed),
+            _ => Err(format!("Unsupported table compression '-C{}'", flags)),
+        }
     }
 }
@@ -0,0 +1,171 @@
+use std::fmt;
+
+// A set of chars stored as sorted inclusive ranges that neither overlap nor
+// touch, so `[^a]` or `.` in UTF-8 mode stay small. Ranges never start or
+// end on a surrogate, which are not chars, but may span them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClassSet {
+    ranges: Vec<(char, char)>,
+}
+
+impl ClassSet {
+    pub fn new() -> Self {
+        ClassSet::default()
+    }
+
+    // Every char from `start` to `end`, empty if `start > end`
+    pub fn range(start: char, end: char) -> Self {
+        ClassSet::from_ranges(vec![(start, end)])
+    }
+
+    pub fn full() -> Self {
+        ClassSet::range('\0', char::MAX)
+    }
+
+    // Ranges in any order, they get sorted and merged
+    pub fn from_ranges(mut ranges: Vec<(char, char)>) -> Self {
+        ranges.retain(|(start, end)| start <= end);
+        ranges.sort();
+
+        let mut merged: Vec<(char, char)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last)) if next_char(*last).is_none_or(|next| next >= start) => *last = (*last).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        ClassSet { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    // Number of chars in the set
+    pub fn len(&self) -> usize {
+        let surrogates = 0xE000 - 0xD800;
+        self.ranges
+            .iter()
+            .map(|&(start, end)| {
+                let count = end as usize - start as usize + 1;
+                if start <= '\u{D7FF}' && end >= '\u{E000}' {
+                    count - surrogates
+                } else {
+                    count
+                }
+            })
+            .sum()
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let index = self.ranges.partition_point(|&(_, end)| end < c);
+        self.ranges.get(index).is_some_and(|&(start, _)| start <= c)
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.ranges.iter().flat_map(|&(start, end)| start..=end)
+    }
+
+    pub fn union(&self, other: &ClassSet) -> ClassSet {
+        ClassSet::from_ranges(self.ranges.iter().chain(&other.ranges).copied().collect())
+    }
+
+    pub fn intersection(&self, other: &ClassSet) -> ClassSet {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (start, end) = self.ranges[i];
+            let (other_start, other_end) = other.ranges[j];
+            if start.max(other_start) <= end.min(other_end) {
+                ranges.push((start.max(other_start), end.min(other_end)));
+            }
+            // The range that ends first can't overlap anything after
+            if end < other_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+
+        ClassSet { ranges }
+    }
+
+    pub fn difference(&self, other: &ClassSet) -> ClassSet {
+        self.intersection(&other.negate())
+    }
+
+    // Every char not in the set
+    pub fn negate(&self) -> ClassSet {
+        let mut ranges = Vec::new();
+        let mut next = Some('\0');
+
+        for &(start, end) in &self.ranges {
+            if let Some(from) = next.filter(|&from| from < start) {
+                ranges.push((from, previous_char(start)));
+            }
+            next = next_char(end);
+        }
+        if let Some(from) = next {
+            ranges.push((from, char::MAX));
+        }
+
+        ClassSet { ranges }
+    }
+
+    // The set with both cases of every ASCII letter in it
+    pub fn fold_case(&self) -> ClassSet {
+        let letters = self.intersection(&ClassSet::from_ranges(vec![('A', 'Z'), ('a', 'z')]));
+        let other_case: ClassSet = letters
+            .chars()
+            .map(|c| if c.is_ascii_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+            .collect();
+        self.union(&other_case)
+    }
+}
+
+impl FromIterator<char> for ClassSet {
+    fn from_iter<I: IntoIterator<Item = char>>(chars: I) -> Self {
+        ClassSet::from_ranges(chars.into_iter().map(|c| (c, c)).collect())
+    }
+}
+
+impl From<char> for ClassSet {
+    fn from(c: char) -> Self {
+        ClassSet::range(c, c)
+    }
+}
+
+// Ranges as in a bracket expression, `a-z0`
+impl fmt::Display for ClassSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &(start, end) in &self.ranges {
+            if start == end {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}-{}", start, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Neighbours of a char, skipping the surrogates
+pub(crate) fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+pub(crate) fn previous_char(c: char) -> char {
+    match c {
+        '\u{E000}' => '\u{D7FF}',
+        _ => char::from_u32(c as u32 - 1).unwrap(),
+    }
+}
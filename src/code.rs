@@ -1,13 +1,102 @@
-use crate::{LexFile, DFA};
+use std::str::FromStr;
 
+use crate::{byte_classes, full_table, ClassSet, CompressedTables, HeadLength, LexFile, DFA};
+
+// How the DFA transitions are written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Switch,     // nested switch statements
+    Compressed, // flex-style yy_base/yy_def/yy_nxt/yy_chk tables
+    Full,       // uncompressed states x 256 table
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "switch" => Ok(Backend::Switch),
+            "compressed" | "table" => Ok(Backend::Compressed),
+            "full" => Ok(Backend::Full),
+            _ => Err(format!("Unknown backend '{}'", name)),
+        }
+    }
+}
+
+// Names visible outside of the scanner, renamed by `%option prefix`
+const EXPORTED_NAMES: [&str; 9] = [
+    "yylex",
+    "yytext",
+    "yyleng",
+    "yylineno",
+    "yycolumn",
+    "yylloc",
+    "yyin",
+    "yywrap",
+    "yy_flex_debug",
+];
+
+// The output follows `file.options`
 pub struct CodeGenerator {
     file: LexFile,
     dfa: DFA,
+    backend: Backend,
 }
 
 impl CodeGenerator {
-    pub fn new(file: LexFile, dfa: DFA) -> Self {        
-        CodeGenerator { file, dfa }
+    pub fn new(file: LexFile, dfa: DFA, backend: Backend) -> Self {
+        CodeGenerator { file, dfa, backend }
+    }
+
+    // Header for `--header-file`, declares what other files need to call
+    // the scanner
+    pub fn header(&self) -> String {
+        let options = &self.file.options;
+        let guard = format!("{}LEX_H", options.prefix.as_deref().unwrap_or("yy").to_uppercase());
+        let mut header = String::new();
+
+        header.push_str(&format!("#ifndef {}\n", guard));
+        header.push_str(&format!("#define {}\n", guard));
+        header.push('\n');
+        header.push_str("#include <stdio.h>\n");
+        header.push('\n');
+        header.push_str(&self.generate_prefix_defines());
+        header.push_str("extern int yylex(void);\n");
+        if options.yywrap {
+            header.push_str("extern int yywrap(void);\n");
+        }
+        header.push_str("extern char *yytext;\n");
+        header.push_str("extern int yyleng;\n");
+        header.push_str("extern int yylineno;\n");
+        header.push_str("extern int yycolumn;\n");
+        header.push_str("extern FILE *yyin;\n");
+        if options.debug {
+            header.push_str("extern int yy_flex_debug;\n");
+        }
+        header.push('\n');
+        header.push_str(YYLTYPE);
+        header.push_str("#endif\n");
+        header.push_str("extern YYLTYPE yylloc;\n");
+        header.push('\n');
+        header.push_str(&format!("#endif // {}\n", guard));
+
+        header
+    }
+
+    fn generate_prefix_defines(&self) -> String {
+        let options = &self.file.options;
+        let Some(prefix) = &options.prefix else {
+            return String::new();
+        };
+
+        let mut defines = String::new();
+        // With noyywrap, yywrap() is a macro of its own
+        for name in EXPORTED_NAMES.into_iter().filter(|&name| name != "yywrap" || options.yywrap) {
+            defines.push_str(&format!("#define {} {}\n", name, name.replacen("yy", prefix, 1)));
+        }
+        defines.push('\n');
+
+        defines
     }
 
     pub fn code(&self) -> String {
@@ -33,85 +122,125 @@ impl CodeGenerator {
         // This includes standard includes, types, etc.
         let mut header = String::new();
 
-        println!("{:?}", self.file.definitions_code);
+        // Before the user code, which may use the exported names too
+        header.push_str(&self.generate_prefix_defines());
+
         for line in &self.file.definitions_code {
             header.push_str(line);
-            header.push_str("\n");
+            header.push('\n');
         }
 
         header.push_str("#include \"libl.h\"\n");
+        let options = &self.file.options;
+        if options.prefix.is_some() {
+            // libl only defines the globals under their `yy` names
+            header.push_str("char *yytext = NULL;\n");
+            header.push_str("int yyleng = 0;\n");
+            header.push_str("int yylineno = 1;\n");
+            header.push_str("int yycolumn = 1;\n");
+            header.push_str("FILE *yyin = NULL;\n");
+            if options.yywrap {
+                header.push_str("__attribute__((weak)) int yywrap(void) { return 1; }\n");
+            }
+        }
+        if !options.yywrap {
+            // %option noyywrap: the scanner stops at the first end of input
+            header.push_str("#define yywrap() 1\n");
+        }
+        if options.debug {
+            header.push_str("int yy_flex_debug = 1; // Set to 0 to silence the rule trace\n");
+        }
+        header.push('\n');
+        header.push_str("// Where the current token starts and ends, the parser defines yylloc\n");
+        header.push_str("// when it includes a bison header with locations\n");
+        header.push_str(YYLTYPE);
+        header.push_str("YYLTYPE yylloc = {1, 1, 1, 1};\n");
+        header.push_str("#endif\n");
+        header.push('\n');
+        header.push_str("// Run before every action, for example to adjust yylloc\n");
+        header.push_str("#ifndef YY_USER_ACTION\n");
+        header.push_str("#define YY_USER_ACTION\n");
+        header.push_str("#endif\n");
+        header.push('\n');
+        header.push_str("#ifndef YY_BUFFER_SIZE\n");
         header.push_str("#define YY_BUFFER_SIZE 16384\n");
+        header.push_str("#endif\n");
         header.push_str("#define ECHO printf(\"%s\\n\", yytext)\n");
-        header.push_str("static int yy_rejected = 0; // Flag indicating REJECT was called\n");
-        header.push_str("#define REJECT do {  \\\n");
-        header.push_str("    yy_rejected = 1; \\\n");
-        header.push_str("    return ;  \\\n");
-        header.push_str("} while (0)\n");
-        header.push_str("\n");
-        header.push_str("\n");
+        header.push_str("// Go on with the next best match instead, only valid inside actions\n");
+        header.push_str("#define REJECT goto yy_reject\n");
+        header.push_str("// Stop scanning, yylex() returns 0\n");
+        header.push_str("#define yyterminate() goto yy_terminate\n");
+        if options.input {
+            header.push_str("static int input(void);\n");
+        }
+        if options.unput {
+            header.push_str("static void unput(int c);\n");
+        }
+        header.push('\n');
+        header.push_str(&self.generate_start_conditions());
+        header.push('\n');
 
         header
     }
 
+    fn generate_start_conditions(&self) -> String {
+        let mut conditions = String::new();
+
+        conditions.push_str("// Start conditions\n");
+        for (index, condition) in self.file.start_conditions.iter().enumerate() {
+            conditions.push_str(&format!("#define {} {}\n", condition.name, index));
+        }
+        conditions.push_str("static int yy_start = 0; // Current start condition\n");
+        conditions.push_str("#define BEGIN yy_start = \n");
+        conditions.push_str("#define YY_START yy_start\n");
+        conditions.push_str("#define YYSTATE YY_START\n");
+
+        if self.file.options.stack {
+            conditions.push('\n');
+            conditions.push_str(START_CONDITION_STACK);
+        }
+
+        conditions
+    }
+
     fn generate_transition_table(&self) -> String {
         // Generate code for the DFA transition table
         let mut table_code = String::new();
 
         // Define state type
         table_code.push_str("typedef int StateID;\n");
-        table_code.push_str("\n");
+        table_code.push('\n');
 
-        // Generate the transition table as a 2D array or switch statement
-        table_code.push_str("static StateID transition(StateID state, unsigned char c) {\n");
-        table_code.push_str("    switch(state) {\n");
+        // DFA start states, indexed by 2 * start condition + at beginning of line
+        let start_states: Vec<String> = self.dfa.start_states.iter().map(|s| s.to_string()).collect();
+        table_code.push_str(&format!(
+            "static const StateID yy_start_states[] = {{ {} }};\n",
+            start_states.join(", ")
+        ));
+        table_code.push('\n');
 
-        // For each state, generate its transitions
-        for state in &self.dfa.states {
-            table_code.push_str(&format!("    case {}:\n", state));
-            table_code.push_str("        switch(c) {\n");
+        // Equivalence class of each input byte, transitions are on classes
+        table_code.push_str(&c_array("yy_ec", &byte_classes(&self.dfa)));
 
-            // Find all transitions from this state
-            for ((from_state, symbol), to_state) in &self.dfa.transitions {
-                if from_state == state {
-                    if let crate::TransitionSymbol::Char(ch) = symbol {
-                        // Use ASCII code instead of character literal
-                        let ascii_code = *ch as u8;
-                        table_code.push_str(&format!(
-                            "            case {}: // {}\n",
-                            ascii_code,
-                            char_description(*ch)
-                        ));
-                        table_code.push_str(&format!("                return {};\n", to_state));
-                    }
-                }
-            }
+        table_code.push_str(&match self.backend {
+            Backend::Switch => self.generate_switch_transitions(),
+            Backend::Compressed => self.generate_compressed_transitions(),
+            Backend::Full => self.generate_full_transitions(),
+        });
 
-            table_code.push_str("            default:\n");
-            table_code.push_str("                return -1; // Error state\n");
-            table_code.push_str("        }\n");
-        }
-
-        table_code.push_str("    default:\n");
-        table_code.push_str("        return -1; // Error state\n");
-        table_code.push_str("    }\n");
-        table_code.push_str("}\n");
-        table_code.push_str("\n");
+        table_code.push_str(&self.generate_trailing_context());
 
-        // Generate final states check
-        table_code.push_str("static int is_accepting(StateID state) {\n");
-        table_code.push_str("    switch(state) {\n");
-
-        for state in &self.dfa.final_states {
-            table_code.push_str(&format!("    case {}:\n", state));
-            table_code.push_str("        return 1;\n");
+        if self.file.options.debug {
+            // Line of each rule in the spec for the -d trace, 0 when unknown
+            let lines: Vec<i32> = self
+                .file
+                .rules
+                .iter()
+                .map(|rule| rule.location.as_ref().map_or(0, |location| location.line as i32))
+                .collect();
+            table_code.push_str(&c_array("yy_rule_lines", &lines));
         }
 
-        table_code.push_str("    default:\n");
-        table_code.push_str("        return 0;\n");
-        table_code.push_str("    }\n");
-        table_code.push_str("}\n");
-        table_code.push_str("\n");
-
         // Structure to associate state with pattern/rule ID
         table_code.push_str("struct PatternInfo {\n");
         table_code.push_str("    int pattern_id;\n");
@@ -123,58 +252,425 @@ impl CodeGenerator {
         table_code.push_str("    struct PatternInfo info = {-1, -1};\n");
         table_code.push_str("    switch(state) {\n");
 
-        // Assign a pattern ID and priority for each final state
-        // Priority should be based on the rule order (earlier rules have higher priority)
-        let mut pattern_id = 0;
+        // The pattern ID is the index of the rule matched in each final state,
+        // earlier rules have higher priority
+        let rule_count = self.file.rules.len();
         for state in &self.dfa.final_states {
+            let Some(rule) = self.dfa.rule_index(*state) else {
+                continue;
+            };
             table_code.push_str(&format!("    case {}:\n", state));
-            table_code.push_str(&format!("        info.pattern_id = {};\n", pattern_id));
-            // Higher priority for earlier patterns (reverse of pattern_id)
-            table_code.push_str(&format!("        info.priority = {};\n", self.dfa.final_states.len() - pattern_id));
+            table_code.push_str(&format!("        info.pattern_id = {};\n", rule));
+            table_code.push_str(&format!("        info.priority = {};\n", rule_count - rule));
             table_code.push_str("        break;\n");
-            pattern_id += 1;
         }
 
         table_code.push_str("    }\n");
         table_code.push_str("    return info;\n");
         table_code.push_str("}\n\n");
 
-        // Generate function to execute the correct action based on state
-        table_code.push_str("static void execute_action(StateID state) {\n");
-        table_code.push_str("    switch(state) {\n");
+        table_code
+    }
 
-        for (state, action) in &self.dfa.actions {
-            table_code.push_str(&format!("    case {}:\n", state));
-            table_code.push_str(&format!("        {}\n", action));
-            table_code.push_str("        break;\n");
+    fn generate_switch_transitions(&self) -> String {
+        let mut code = String::new();
+
+        // Generate the transition function as nested switch statements
+        code.push_str("static StateID transition(StateID state, unsigned char c) {\n");
+        code.push_str("    switch(state) {\n");
+
+        // For each state, generate its transitions
+        for state in &self.dfa.states {
+            code.push_str(&format!("    case {}:\n", state));
+            code.push_str("        switch(yy_ec[c]) {\n");
+
+            // Find all transitions from this state
+            for ((from_state, class), to_state) in &self.dfa.transitions {
+                if from_state == state {
+                    code.push_str(&format!(
+                        "            case {}: // {}\n",
+                        class,
+                        class_description(&self.dfa.class_set(*class))
+                    ));
+                    code.push_str(&format!("                return {};\n", to_state));
+                }
+            }
+
+            code.push_str("            default:\n");
+            code.push_str("                return -1; // Error state\n");
+            code.push_str("        }\n");
         }
 
-        table_code.push_str("    default:\n");
-        table_code.push_str("        // No action for this state\n");
-        table_code.push_str("        break;\n");
-        table_code.push_str("    }\n");
-        table_code.push_str("}\n");
-        table_code.push_str("\n");
+        code.push_str("    default:\n");
+        code.push_str("        return -1; // Error state\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
 
-        table_code
+        // Generate final states check
+        code.push_str("static int is_accepting(StateID state) {\n");
+        code.push_str("    switch(state) {\n");
+
+        for state in &self.dfa.final_states {
+            code.push_str(&format!("    case {}:\n", state));
+            code.push_str("        return 1;\n");
+        }
+
+        code.push_str("    default:\n");
+        code.push_str("        return 0;\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code
+    }
+
+    fn generate_compressed_transitions(&self) -> String {
+        let tables = CompressedTables::new(&self.dfa);
+        let mut code = String::new();
+
+        code.push_str(&c_array("yy_base", &tables.base));
+        code.push_str(&c_array("yy_def", &tables.default));
+        code.push_str(&c_array("yy_nxt", &tables.next));
+        code.push_str(&c_array("yy_chk", &tables.check));
+        code.push_str(&self.generate_accept_table());
+
+        code.push_str("static StateID transition(StateID state, unsigned char c) {\n");
+        code.push_str("    while (state != -1) {\n");
+        code.push_str("        int i = yy_base[state] + yy_ec[c];\n");
+        code.push_str("        if (yy_chk[i] == state) {\n");
+        code.push_str("            return yy_nxt[i];\n");
+        code.push_str("        }\n");
+        code.push_str("        state = yy_def[state];\n");
+        code.push_str("    }\n");
+        code.push_str("    return -1; // Error state\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str(ACCEPT_LOOKUP);
+
+        code
+    }
+
+    fn generate_full_transitions(&self) -> String {
+        let table = full_table(&self.dfa);
+        let mut code = String::new();
+
+        code.push_str(&format!(
+            "static const {} yy_nxt[][{}] = {{\n",
+            c_int_type(table.iter().flatten()),
+            self.dfa.class_count()
+        ));
+        for row in &table {
+            let entries: Vec<String> = row.iter().map(|next| next.to_string()).collect();
+            code.push_str(&format!("    {{ {} }},\n", entries.join(", ")));
+        }
+        code.push_str("};\n");
+        code.push('\n');
+        code.push_str(&self.generate_accept_table());
+
+        code.push_str("static StateID transition(StateID state, unsigned char c) {\n");
+        code.push_str("    return yy_nxt[state][yy_ec[c]];\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str(ACCEPT_LOOKUP);
+
+        code
+    }
+
+    // yy_accept[state] is non-zero for accepting states
+    fn generate_accept_table(&self) -> String {
+        let count = self.dfa.states.iter().max().map_or(0, |max| max + 1);
+        let accept: Vec<i32> = (0..count)
+            .map(|state| self.dfa.final_states.contains(&state) as i32)
+            .collect();
+
+        c_array("yy_accept", &accept)
+    }
+
+    fn generate_trailing_context(&self) -> String {
+        // Positions where the head of a variable-length `r/s` rule ended
+        let mut code = String::new();
+
+        let markers = self
+            .dfa
+            .head_ends
+            .values()
+            .flatten()
+            .max()
+            .map_or(1, |max| max + 1);
+
+        code.push_str(&format!("static int yy_head_pos[{}];\n", markers));
+        code.push('\n');
+
+        code.push_str("static void yy_mark_head_ends(StateID state, int pos) {\n");
+        code.push_str("    switch(state) {\n");
+        for (state, markers) in &self.dfa.head_ends {
+            code.push_str(&format!("    case {}:\n", state));
+            for marker in markers {
+                code.push_str(&format!("        yy_head_pos[{}] = pos;\n", marker));
+            }
+            code.push_str("        break;\n");
+        }
+        code.push_str("    default:\n");
+        code.push_str("        (void)pos;\n");
+        code.push_str("        (void)yy_head_pos;\n");
+        code.push_str("        break;\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        // Length of the text to keep when a match ends in `state`
+        code.push_str("static int yy_head_length(StateID state, int length) {\n");
+        code.push_str("    switch(state) {\n");
+        for (state, head_length) in &self.dfa.head_lengths {
+            code.push_str(&format!("    case {}:\n", state));
+            code.push_str(&format!("        return {};\n", Self::head_length_expression(Some(head_length))));
+        }
+        code.push_str("    default:\n");
+        code.push_str("        return length;\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code
+    }
+
+    // C expression for the length of the text to keep out of `length`
+    fn head_length_expression(head_length: Option<&HeadLength>) -> String {
+        match head_length {
+            Some(HeadLength::Fixed(head)) => head.to_string(),
+            Some(HeadLength::FromTail(tail)) => format!("length - {}", tail),
+            Some(HeadLength::Marker(marker)) => format!("yy_head_pos[{}]", marker),
+            None => "length".to_string(),
+        }
+    }
+
+    // Lower priority rules that match the same text as the rule of a state.
+    // They sort after it, so REJECT tries them before shorter matches.
+    fn generate_fallback_matches(&self) -> String {
+        let mut code = String::new();
+        let rule_count = self.file.rules.len();
+
+        code.push_str("// Other rules matching the text of an accepting state, for REJECT\n");
+        code.push_str("static void yy_add_fallback_matches(StateID state, int length) {\n");
+        code.push_str("    switch(state) {\n");
+        for (state, fallbacks) in &self.dfa.fallback_rules {
+            code.push_str(&format!("    case {}:\n", state));
+            for (rule, head_length) in fallbacks {
+                code.push_str(&format!(
+                    "        yy_push_match(state, {}, {}, length, {});\n",
+                    rule,
+                    rule_count - rule,
+                    Self::head_length_expression(head_length.as_ref())
+                ));
+            }
+            code.push_str("        break;\n");
+        }
+        code.push_str("    default:\n");
+        code.push_str("        (void)length;\n");
+        code.push_str("        break;\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code
+    }
+
+    fn generate_buffer(&self) -> String {
+        // Input buffer that grows when a single token does not fit, so the
+        // text of the current token is never split
+        let mut buffer = String::new();
+
+        buffer.push_str("// Input buffer\n");
+        buffer.push_str("static char *yy_buffer = NULL;\n");
+        buffer.push_str("static size_t yy_buffer_size = 0;\n");
+        buffer.push_str("static int yy_input_done = 0; // fread() hit the end of yyin\n");
+        buffer.push_str("static char *yy_current_pos = NULL; // Next char to scan\n");
+        buffer.push_str("static char *yy_buffer_end = NULL;\n");
+        buffer.push('\n');
+
+        buffer.push_str("// Read more input, keeping the text from *token_start on and moving it to\n");
+        buffer.push_str("// the front of the buffer. Returns the number of bytes read, 0 at end of yyin.\n");
+        buffer.push_str("static size_t yy_fill_buffer(char **token_start, char **buffer_end) {\n");
+        buffer.push_str("    if (yy_buffer == NULL) {\n");
+        buffer.push_str("        yy_buffer_size = YY_BUFFER_SIZE;\n");
+        buffer.push_str("        yy_buffer = (char *)malloc(yy_buffer_size);\n");
+        buffer.push_str("        if (!yy_buffer) {\n");
+        buffer.push_str("            fprintf(stderr, \"Out of memory allocating input buffer\\n\");\n");
+        buffer.push_str("            exit(1);\n");
+        buffer.push_str("        }\n");
+        buffer.push_str("        *token_start = *buffer_end = yy_buffer;\n");
+        buffer.push_str("    }\n");
+        buffer.push('\n');
+        buffer.push_str("    if (yy_input_done) {\n");
+        buffer.push_str("        return 0;\n");
+        buffer.push_str("    }\n");
+        buffer.push('\n');
+        buffer.push_str("    // Drop the consumed input before the current token\n");
+        buffer.push_str("    size_t kept = *buffer_end - *token_start;\n");
+        buffer.push_str("    if (*token_start != yy_buffer) {\n");
+        buffer.push_str("        memmove(yy_buffer, *token_start, kept);\n");
+        buffer.push_str("    }\n");
+        buffer.push('\n');
+        buffer.push_str("    // The token fills the whole buffer, make it bigger\n");
+        buffer.push_str("    if (kept == yy_buffer_size) {\n");
+        buffer.push_str("        yy_buffer_size *= 2;\n");
+        buffer.push_str("        yy_buffer = (char *)realloc(yy_buffer, yy_buffer_size);\n");
+        buffer.push_str("        if (!yy_buffer) {\n");
+        buffer.push_str("            fprintf(stderr, \"Out of memory growing input buffer\\n\");\n");
+        buffer.push_str("            exit(1);\n");
+        buffer.push_str("        }\n");
+        buffer.push_str("    }\n");
+        buffer.push('\n');
+        buffer.push_str("    if (yyin == NULL) {\n");
+        buffer.push_str("        yyin = stdin;\n");
+        buffer.push_str("    }\n");
+        buffer.push('\n');
+        buffer.push_str("    size_t n = fread(yy_buffer + kept, 1, yy_buffer_size - kept, yyin);\n");
+        buffer.push_str("    if (n == 0) {\n");
+        buffer.push_str("        yy_input_done = 1;\n");
+        buffer.push_str("    }\n");
+        buffer.push('\n');
+        buffer.push_str("    *token_start = yy_buffer;\n");
+        buffer.push_str("    *buffer_end = yy_buffer + kept + n;\n");
+        buffer.push_str("    return n;\n");
+        buffer.push_str("}\n");
+        buffer.push('\n');
+
+        if self.file.options.input {
+            buffer.push_str(&self.generate_input());
+        }
+        if self.file.options.unput {
+            buffer.push_str(UNPUT);
+        }
+
+        buffer
+    }
+
+    // Read the next char of input from an action, EOF at the end. Skipped
+    // with %option noinput.
+    fn generate_input(&self) -> String {
+        let mut input = String::new();
+
+        input.push_str("__attribute__((unused)) static int input(void) {\n");
+        input.push_str("    if (yy_current_pos >= yy_buffer_end && yy_fill_buffer(&yy_current_pos, &yy_buffer_end) == 0) {\n");
+        input.push_str("        return EOF;\n");
+        input.push_str("    }\n");
+        input.push('\n');
+        input.push_str("    int c = (unsigned char)*yy_current_pos++;\n");
+        input.push_str("    yy_at_bol = c == '\\n';\n");
+        input.push_str("    if (c == '\\n') {\n");
+        input.push_str("        yylineno++;\n");
+        input.push_str("        yycolumn = 1;\n");
+        input.push_str("    } else {\n");
+        input.push_str("        yycolumn++;\n");
+        input.push_str("    }\n");
+        input.push_str("    return c;\n");
+        input.push_str("}\n");
+        input.push('\n');
+
+        input
+    }
+
+    // One case per action, rules joined with `|` share theirs. Actions get
+    // braces when they have none, so they can declare variables.
+    fn generate_actions(&self) -> String {
+        let mut actions: Vec<(&String, Vec<usize>)> = Vec::new();
+        for (index, rule) in self.file.rules.iter().enumerate() {
+            match actions.iter_mut().find(|(action, _)| **action == rule.action) {
+                Some((_, rules)) => rules.push(index),
+                None => actions.push((&rule.action, vec![index])),
+            }
+        }
+
+        let mut code = String::new();
+        code.push_str("        switch (match->pattern_id) {\n");
+        for (action, rules) in actions {
+            for rule in rules {
+                code.push_str(&format!("        case {}:\n", rule));
+            }
+            code.push_str(&Self::indent_action(action, "            "));
+            code.push_str("            break;\n");
+        }
+        code.push_str("        }\n");
+
+        code
+    }
+
+    // Run at the end of input, once yywrap() has no more files. Conditions
+    // without an `<<EOF>>` rule of their own stop the scanner.
+    fn generate_eof_actions(&self) -> String {
+        let mut code = String::new();
+        code.push_str("            switch (yy_start) {\n");
+        for rule in self.file.eof_rules.iter().filter(|rule| !rule.start_conditions.is_empty()) {
+            for condition in &rule.start_conditions {
+                code.push_str(&format!("            case {}:\n", condition));
+            }
+            code.push_str(&Self::indent_action(&rule.action, "                "));
+            code.push_str("                break;\n");
+        }
+        code.push_str("            default:\n");
+        match self.file.eof_rules.iter().find(|rule| rule.start_conditions.is_empty()) {
+            Some(rule) => {
+                code.push_str(&Self::indent_action(&rule.action, "                "));
+                code.push_str("                break;\n");
+            }
+            None => code.push_str("                goto end_of_input;\n"),
+        }
+        code.push_str("            }\n");
+
+        code
+    }
+
+    // The lines of an action at the given indentation, in braces when it has
+    // none
+    fn indent_action(action: &str, indent: &str) -> String {
+        let mut code = String::new();
+        let braced = action.starts_with('{');
+        if !braced {
+            code.push_str(indent);
+            code.push_str("{\n");
+        }
+        for line in action.lines() {
+            code.push_str(indent);
+            code.push_str(line);
+            code.push('\n');
+        }
+        if !braced {
+            code.push_str(indent);
+            code.push_str("}\n");
+        }
+        code
     }
 
     fn generate_token_logic(&self) -> String {
         // Generate the token recognition and handling logic
         let mut logic = String::new();
 
+        // The REJECT bookkeeping is left out when no action can jump to it
+        let uses_reject = self.file.uses_reject();
+        let uses_terminate = self
+            .file
+            .rules
+            .iter()
+            .map(|rule| &rule.action)
+            .chain(self.file.eof_rules.iter().map(|rule| &rule.action))
+            .any(|action| action.contains("yyterminate"));
+
         // Define global variables for proper REJECT functionality
         logic.push_str("// Global variables for REJECT and lexer state\n");
-        logic.push_str("static int yy_current_pattern_id = -1;  // Current pattern being matched\n");
-        logic.push_str("static int yy_starting_state = 0;      // DFA start state\n");
-        logic.push_str("static int yy_more_len = 0;            // Length accumulated by yymore()\n");
+        logic.push_str("static int yy_more_flag = 0;           // yymore() was called by the last action\n");
+        logic.push_str("static int yy_more_len = 0;            // Length of the text kept by yymore()\n");
         logic.push_str("static char *yy_current_token_start = NULL; // Start of current token\n");
-        logic.push_str("\n");
+        logic.push_str("static int yy_at_bol = 1;              // Previous char was a newline\n");
+        logic.push('\n');
 
         // Define data structures for tracking matched patterns
         logic.push_str("// Maximum number of patterns that could match a token\n");
         logic.push_str("#define MAX_MATCHES 100\n");
-        logic.push_str("\n");
+        logic.push('\n');
         
         logic.push_str("// Structure to track matched patterns\n");
         logic.push_str("typedef struct {\n");
@@ -182,34 +678,59 @@ impl CodeGenerator {
         logic.push_str("    int pattern_id;     // Pattern ID for this match\n");
         logic.push_str("    int priority;       // Priority of this pattern\n");
         logic.push_str("    int length;         // Length of this match\n");
-        logic.push_str("    char *text_position; // Position in the input where match occurred\n");
+        logic.push_str("    int head_length;    // Length without trailing context\n");
         logic.push_str("} Match;\n");
-        logic.push_str("\n");
+        logic.push('\n');
         
         logic.push_str("// Array to hold all matches for the current token\n");
         logic.push_str("static Match yy_matches[MAX_MATCHES];\n");
         logic.push_str("static int yy_match_count = 0;  // Number of patterns that matched\n");
         logic.push_str("static int yy_match_index = 0;  // Current match being processed\n");
-        logic.push_str("\n");
+        logic.push('\n');
+
+        logic.push_str("// Record a match of a rule\n");
+        logic.push_str("static void yy_push_match(StateID state, int pattern_id, int priority, int length, int head_length) {\n");
+        logic.push_str("    // A match that keeps no text, like the empty head of `a*/b`, would\n");
+        logic.push_str("    // be found again at the same position forever\n");
+        logic.push_str("    if (head_length == 0) {\n");
+        logic.push_str("        return;\n");
+        logic.push_str("    }\n");
+        logic.push('\n');
+        logic.push_str("    // Matches come in increasing length, when the array is full drop\n");
+        logic.push_str("    // the shortest one so the longest match is always kept\n");
+        logic.push_str("    if (yy_match_count == MAX_MATCHES) {\n");
+        logic.push_str("        memmove(yy_matches, yy_matches + 1, (MAX_MATCHES - 1) * sizeof(Match));\n");
+        logic.push_str("        yy_match_count--;\n");
+        logic.push_str("    }\n");
+        logic.push('\n');
+        logic.push_str("    yy_matches[yy_match_count].state = state;\n");
+        logic.push_str("    yy_matches[yy_match_count].pattern_id = pattern_id;\n");
+        logic.push_str("    yy_matches[yy_match_count].priority = priority;\n");
+        logic.push_str("    yy_matches[yy_match_count].length = length;\n");
+        logic.push_str("    yy_matches[yy_match_count].head_length = head_length;\n");
+        logic.push_str("    yy_match_count++;\n");
+        logic.push_str("}\n");
+        logic.push('\n');
+
+        if uses_reject {
+            logic.push_str(&self.generate_fallback_matches());
+        }
 
         // Define function to add a match to our collection
         logic.push_str("// Function to add a match to our collection\n");
         logic.push_str("static void add_match(StateID state, char *pos) {\n");
-        logic.push_str("    if (yy_match_count < MAX_MATCHES) {\n");
-        logic.push_str("        struct PatternInfo info = get_pattern_info(state);\n");
-        logic.push_str("        if (info.pattern_id != -1) {\n");
-        logic.push_str("            yy_matches[yy_match_count].state = state;\n");
-        logic.push_str("            yy_matches[yy_match_count].pattern_id = info.pattern_id;\n");
-        logic.push_str("            yy_matches[yy_match_count].priority = info.priority;\n");
-        logic.push_str("            yy_matches[yy_match_count].length = pos - yy_current_token_start;\n");
-        logic.push_str("            yy_matches[yy_match_count].text_position = pos;\n");
-        logic.push_str("            yy_match_count++;\n");
-        logic.push_str("        }\n");
-        logic.push_str("    } else {\n");
-        logic.push_str("        fprintf(stderr, \"Too many matches for token, increase MAX_MATCHES\\n\");\n");
+        logic.push_str("    struct PatternInfo info = get_pattern_info(state);\n");
+        logic.push_str("    if (info.pattern_id == -1) {\n");
+        logic.push_str("        return;\n");
         logic.push_str("    }\n");
+        logic.push('\n');
+        logic.push_str("    int length = pos - yy_current_token_start;\n");
+        logic.push_str("    yy_push_match(state, info.pattern_id, info.priority, length, yy_head_length(state, length));\n");
+        if uses_reject {
+            logic.push_str("    yy_add_fallback_matches(state, length);\n");
+        }
         logic.push_str("}\n");
-        logic.push_str("\n");
+        logic.push('\n');
         
         // Function to compare matches for sorting
         logic.push_str("// Function to compare matches for sorting by length, then priority\n");
@@ -223,88 +744,119 @@ impl CodeGenerator {
         logic.push_str("    // Then by priority (highest priority first)\n");
         logic.push_str("    return m2->priority - m1->priority;\n");
         logic.push_str("}\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
         // Define yymore() functionality
-        logic.push_str("// Implementation of yymore() function\n");
-        logic.push_str("#define yymore() do { \\\n");
-        logic.push_str("    yy_more_len = yyleng; \\\n");
-        logic.push_str("} while (0)\n");
-        logic.push_str("\n");
+        logic.push_str("// The next token is appended to yytext instead of replacing it\n");
+        logic.push_str("#define yymore() (yy_more_flag = 1)\n");
+        logic.push('\n');
+
+        logic.push_str(&self.generate_buffer());
 
         // Define yylex function which is the main scanning function
         logic.push_str("int yylex(void) {\n");
-        logic.push_str("    static char *current_pos = NULL;\n");
-        logic.push_str("    static char *buffer_end = NULL;\n");
-        logic.push_str("    static char buffer[YY_BUFFER_SIZE];\n");
         logic.push_str("    static char *yytext_buffer = NULL;\n");
         logic.push_str("    static int yytext_buffer_size = 0;\n");
-        logic.push_str("\n");
-
-        logic.push_str("    // Initialize buffer if first call\n");
-        logic.push_str("    if (current_pos == NULL || current_pos >= buffer_end) {\n");
-        logic.push_str("        if ((current_pos = buffer_end = buffer) == NULL)\n");
-        logic.push_str("            return 0; // EOF\n");
-        logic.push_str("        int n = fread(buffer, 1, YY_BUFFER_SIZE, yyin);\n");
-        logic.push_str("        buffer_end = buffer + n;\n");
-        logic.push_str("        if (n == 0) return 0; // EOF\n");
-        logic.push_str("    }\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
         logic.push_str("scan_token:\n");
+        logic.push_str("    // Keep the text of the last token when its action called yymore()\n");
+        logic.push_str("    yy_more_len = yy_more_flag ? yyleng : 0;\n");
+        logic.push_str("    yy_more_flag = 0;\n");
+        logic.push('\n');
+        logic.push_str("    // Refill the buffer once it is used up, moving on to the next\n");
+        logic.push_str("    // file when yywrap() returns 0\n");
+        logic.push_str("    while (yy_current_pos >= yy_buffer_end) {\n");
+        logic.push_str("        if (yy_fill_buffer(&yy_current_pos, &yy_buffer_end) > 0) {\n");
+        logic.push_str("            continue;\n");
+        logic.push_str("        }\n");
+        logic.push_str("        if (yywrap()) {\n");
+        if self.file.options.debug {
+            logic.push_str("            if (yy_flex_debug) {\n");
+            logic.push_str("                fprintf(stderr, \"--EOF (start condition %d)\\n\", YY_START);\n");
+            logic.push_str("            }\n");
+        }
+        if self.file.eof_rules.is_empty() {
+            logic.push_str("            goto end_of_input;\n");
+        } else {
+            logic.push_str("            // An action that neither returns nor calls yyterminate() is\n");
+            logic.push_str("            // expected to point yyin at more input\n");
+            logic.push_str(&self.generate_eof_actions());
+        }
+        logic.push_str("        }\n");
+        logic.push_str("        yy_input_done = 0;\n");
+        logic.push_str("        yy_at_bol = 1;\n");
+        logic.push_str("    }\n");
+        logic.push('\n');
+
         logic.push_str("    // Reset match tracking for a new token\n");
         logic.push_str("    yy_match_count = 0;\n");
         logic.push_str("    yy_match_index = 0;\n");
-        logic.push_str("    yy_current_token_start = current_pos;\n");
-        logic.push_str("\n");
+        logic.push_str("    yy_current_token_start = yy_current_pos;\n");
+        logic.push('\n');
         logic.push_str("    // Run the DFA to find all potential matches\n");
-        logic.push_str("    char *scan_pos = current_pos;\n");
-        logic.push_str("    StateID current_state = yy_starting_state;\n");
-        logic.push_str("\n");
-        logic.push_str("    while (scan_pos < buffer_end) {\n");
+        logic.push_str("    char *scan_pos = yy_current_pos;\n");
+        logic.push_str("    StateID current_state = yy_start_states[yy_start * 2 + yy_at_bol];\n");
+        logic.push_str("    yy_mark_head_ends(current_state, 0);\n");
+        logic.push('\n');
+        logic.push_str("    for (;;) {\n");
+        logic.push_str("        // Read more input when a token reaches the end of the buffer\n");
+        logic.push_str("        if (scan_pos >= yy_buffer_end) {\n");
+        logic.push_str("            size_t scanned = scan_pos - yy_current_pos;\n");
+        logic.push_str("            size_t n = yy_fill_buffer(&yy_current_pos, &yy_buffer_end);\n");
+        logic.push_str("            yy_current_token_start = yy_current_pos;\n");
+        logic.push_str("            scan_pos = yy_current_pos + scanned;\n");
+        logic.push_str("            if (n == 0) {\n");
+        logic.push_str("                break; // End of input\n");
+        logic.push_str("            }\n");
+        logic.push_str("        }\n");
+        logic.push('\n');
         logic.push_str("        unsigned char c = (unsigned char)*scan_pos;\n");
         logic.push_str("        StateID next_state = transition(current_state, c);\n");
-        logic.push_str("\n");
+        logic.push('\n');
         logic.push_str("        if (next_state == -1) {\n");
         logic.push_str("            break; // No valid transition\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
+        logic.push('\n');
         logic.push_str("        current_state = next_state;\n");
         logic.push_str("        scan_pos++;\n");
-        logic.push_str("\n");
+        logic.push_str("        yy_mark_head_ends(current_state, scan_pos - yy_current_pos);\n");
+        logic.push('\n');
         logic.push_str("        // If we've reached an accepting state, record this match\n");
         logic.push_str("        if (is_accepting(current_state)) {\n");
         logic.push_str("            add_match(current_state, scan_pos);\n");
         logic.push_str("        }\n");
         logic.push_str("    }\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
         logic.push_str("    // If we found matches, sort them by length and priority\n");
         logic.push_str("    if (yy_match_count > 0) {\n");
         logic.push_str("        qsort(yy_matches, yy_match_count, sizeof(Match), compare_matches);\n");
-        logic.push_str("\n");
-        logic.push_str("        // Process each match in order until one is not REJECTed\n");
-        logic.push_str("process_match:\n");
+        logic.push('\n');
+        if uses_reject {
+            logic.push_str("        // Process each match in order until one is not REJECTed\n");
+            logic.push_str("process_match:\n");
+        }
         logic.push_str("        // If we've tried all matches, move to the next character and try again\n");
         logic.push_str("        if (yy_match_index >= yy_match_count) {\n");
-        logic.push_str("            if (current_pos < buffer_end) {\n");
-        logic.push_str("                fprintf(stderr, \"All matches REJECTed, skipping character '%c'\\n\", *current_pos);\n");
-        logic.push_str("                current_pos++;\n");
+        logic.push_str("            if (yy_current_pos < yy_buffer_end) {\n");
+        logic.push_str("                fprintf(stderr, \"All matches REJECTed, skipping character '%c'\\n\", *yy_current_pos);\n");
+        logic.push_str("                yy_at_bol = *yy_current_pos == '\\n';\n");
+        logic.push_str("                yy_current_pos++;\n");
         logic.push_str("                goto scan_token;\n");
         logic.push_str("            } else {\n");
         logic.push_str("                // End of buffer, no more tokens\n");
         logic.push_str("                return 0;\n");
         logic.push_str("            }\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
         logic.push_str("        // Get the current match to process\n");
         logic.push_str("        Match *match = &yy_matches[yy_match_index];\n");
-        logic.push_str("        yy_current_pattern_id = match->pattern_id;\n");
-        logic.push_str("\n");
+        logic.push('\n');
         logic.push_str("        // Set up yytext and yyleng based on this match\n");
-        logic.push_str("        yyleng = match->length;\n");
-        logic.push_str("\n");
+        logic.push_str("        yyleng = match->head_length;\n");
+        logic.push('\n');
         logic.push_str("        // Allocate or reallocate yytext buffer if needed\n");
         logic.push_str("        int total_len = yy_more_len + yyleng;\n");
         logic.push_str("        if (yytext_buffer == NULL || total_len + 1 > yytext_buffer_size) {\n");
@@ -316,92 +868,257 @@ impl CodeGenerator {
         logic.push_str("            }\n");
         logic.push_str("            yytext_buffer_size = new_size;\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
+        logic.push('\n');
         logic.push_str("        if (!yytext_buffer) {\n");
         logic.push_str("            fprintf(stderr, \"Out of memory allocating yytext\\n\");\n");
         logic.push_str("            exit(1);\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
+        logic.push('\n');
         logic.push_str("        // Copy new text to yytext (after any text kept by yymore())\n");
-        logic.push_str("        memcpy(yytext_buffer + yy_more_len, current_pos, yyleng);\n");
+        logic.push_str("        memcpy(yytext_buffer + yy_more_len, yy_current_pos, yyleng);\n");
         logic.push_str("        yytext_buffer[total_len] = '\\0';\n");
         logic.push_str("        yytext = yytext_buffer;\n");
         logic.push_str("        yyleng = total_len; // Update yyleng to include yymore text\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
-        logic.push_str("        // Execute the associated action\n");
-        logic.push_str("        yy_rejected = 0;  // Reset REJECT flag before action\n");
-        logic.push_str("        execute_action(match->state);\n");
-        logic.push_str("\n");
-        
-        logic.push_str("        // If action called REJECT, try the next match\n");
-        logic.push_str("        if (yy_rejected) {\n");
-        logic.push_str("            yy_match_index++;\n");
-        logic.push_str("            goto process_match;\n");
+        if self.file.options.debug {
+            logic.push_str("        if (yy_flex_debug) {\n");
+            logic.push_str("            fprintf(stderr, \"--accepting rule at line %d (\\\"%s\\\")\\n\",\n");
+            logic.push_str("                    yy_rule_lines[match->pattern_id], yytext);\n");
+            logic.push_str("        }\n");
+            logic.push('\n');
+        }
+
+        logic.push_str("        // Move past the matched text before the action, so that input()\n");
+        logic.push_str("        // and unput() work from there (trailing context is not consumed\n");
+        logic.push_str("        // and gets scanned again)\n");
+        if uses_reject {
+            logic.push_str("        int at_bol = yy_at_bol;\n");
+        }
+        logic.push_str("        yy_current_pos = yy_current_token_start + match->head_length;\n");
+        logic.push_str("        yy_at_bol = yy_current_pos[-1] == '\\n';\n");
+        logic.push('\n');
+
+        logic.push_str("        // Record where the token is, then move yylineno and yycolumn past\n");
+        logic.push_str("        // it. Text kept by yymore() already set the start.\n");
+        if uses_reject {
+            logic.push_str("        int lineno = yylineno, column = yycolumn;\n");
+        }
+        logic.push_str("        if (yy_more_len == 0) {\n");
+        logic.push_str("            yylloc.first_line = yylineno;\n");
+        logic.push_str("            yylloc.first_column = yycolumn;\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
-        
-        logic.push_str("        // Update the current position to after the matched text\n");
-        logic.push_str("        current_pos = current_pos + match->length;\n");
-        logic.push_str("\n");
-        
-        logic.push_str("        // Reset yymore state for next token (unless yymore() was called)\n");
-        logic.push_str("        if (!yy_more_len) {\n");
-        logic.push_str("            yy_current_pattern_id = -1;\n");
-        logic.push_str("        } else {\n");
-        logic.push_str("            // yymore() was called, keep accumulated text\n");
-        logic.push_str("            // (yy_more_len is already set by yymore macro)\n");
+        logic.push_str("        for (int i = 0; i < match->head_length; i++) {\n");
+        logic.push_str("            yylloc.last_line = yylineno;\n");
+        logic.push_str("            yylloc.last_column = yycolumn;\n");
+        logic.push_str("            if (yy_current_token_start[i] == '\\n') {\n");
+        logic.push_str("                yylineno++;\n");
+        logic.push_str("                yycolumn = 1;\n");
+        if self.file.options.utf8 {
+            // Columns count code points, continuation bytes are skipped
+            logic.push_str("            } else if ((yy_current_token_start[i] & 0xC0) != 0x80) {\n");
+        } else {
+            logic.push_str("            } else {\n");
+        }
+        logic.push_str("                yycolumn++;\n");
+        logic.push_str("            }\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
-        
-        logic.push_str("        // Scan for the next token\n");
+        logic.push('\n');
+
+        logic.push_str("        // Run the action of the matched rule. An action can return a token\n");
+        logic.push_str("        // from yylex(), otherwise scanning goes on with the next token.\n");
+        logic.push_str("        YY_USER_ACTION\n");
+        logic.push_str(&self.generate_actions());
         logic.push_str("        goto scan_token;\n");
+
+        if uses_reject {
+            logic.push('\n');
+            logic.push_str("yy_reject:\n");
+            logic.push_str("        // REJECT: rescan from the same start and take the next best match\n");
+            logic.push_str("        yy_current_pos = yy_current_token_start;\n");
+            logic.push_str("        yy_at_bol = at_bol;\n");
+            logic.push_str("        yylineno = lineno;\n");
+            logic.push_str("        yycolumn = column;\n");
+            logic.push_str("        yy_match_index++;\n");
+            logic.push_str("        goto process_match;\n");
+        }
         logic.push_str("    }\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
         // Handle case where no match was found
         logic.push_str("    // No match found - either EOF or an error\n");
-        logic.push_str("    if (current_pos < buffer_end) {\n");
+        logic.push_str("    if (yy_current_pos < yy_buffer_end) {\n");
         logic.push_str("        // Print error for unrecognized character\n");
         logic.push_str("        fprintf(stderr, \"Lexer error: Unexpected character '");
         logic.push_str("%c' (0x%02X) at line %d, column %d\\n\",\n");
         logic.push_str(
-            "                (*current_pos >= 32 && *current_pos <= 126) ? *current_pos : '?',\n",
+            "                (*yy_current_pos >= 32 && *yy_current_pos <= 126) ? *yy_current_pos : '?',\n",
         );
-        logic.push_str("                (unsigned char)*current_pos, yylineno, yycolumn);\n");
-        logic.push_str("\n");
+        logic.push_str("                (unsigned char)*yy_current_pos, yylineno, yycolumn);\n");
+        logic.push('\n');
         
         logic.push_str("        // Update line/column tracking\n");
-        logic.push_str("        if (*current_pos == '\\n') {\n");
+        logic.push_str("        if (*yy_current_pos == '\\n') {\n");
         logic.push_str("            yylineno++;\n");
-        logic.push_str("            yycolumn = 0;\n");
+        logic.push_str("            yycolumn = 1;\n");
         logic.push_str("        } else {\n");
         logic.push_str("            yycolumn++;\n");
         logic.push_str("        }\n");
-        logic.push_str("\n");
+        logic.push('\n');
         
         logic.push_str("        // Skip invalid character and continue\n");
-        logic.push_str("        current_pos++;\n");
+        logic.push_str("        yy_at_bol = *yy_current_pos == '\\n';\n");
+        logic.push_str("        yy_current_pos++;\n");
         logic.push_str("        goto scan_token;\n");
         logic.push_str("    }\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
+        if uses_terminate {
+            logic.push_str("yy_terminate:\n");
+            logic.push_str("    // Keep the buffered input when an action stopped the scanner early,\n");
+            logic.push_str("    // the next call of yylex() goes on from there\n");
+            logic.push_str("    if (!yy_input_done || yy_current_pos < yy_buffer_end) {\n");
+            logic.push_str("        return 0;\n");
+            logic.push_str("    }\n");
+            logic.push('\n');
+        }
+        // Without a default <<EOF>> action some condition jumps here
+        if !self.file.eof_rules.iter().any(|rule| rule.start_conditions.is_empty()) {
+            logic.push_str("end_of_input:\n");
+        }
         logic.push_str("    // Clean up at EOF\n");
         logic.push_str("    if (yytext_buffer) {\n");
         logic.push_str("        free(yytext_buffer);\n");
         logic.push_str("        yytext_buffer = NULL;\n");
         logic.push_str("        yytext = NULL;\n");
         logic.push_str("    }\n");
-        logic.push_str("\n");
+        logic.push('\n');
+        logic.push_str("    free(yy_buffer);\n");
+        logic.push_str("    yy_buffer = NULL;\n");
+        logic.push_str("    yy_current_pos = yy_buffer_end = NULL;\n");
+        logic.push_str("    yy_input_done = 0;\n");
+        logic.push('\n');
         
         logic.push_str("    return 0; // EOF\n");
         logic.push_str("}\n");
-        logic.push_str("\n");
+        logic.push('\n');
 
         logic
     }
 }
 
+// yy_push_state() and friends for %option stack
+const START_CONDITION_STACK: &str = "\
+static int *yy_state_stack = NULL;
+static int yy_state_stack_depth = 0;
+static int yy_state_stack_size = 0;
+
+// Switch to a start condition, remembering the current one
+__attribute__((unused)) static void yy_push_state(int state) {
+    if (yy_state_stack_depth == yy_state_stack_size) {
+        yy_state_stack_size = yy_state_stack_size ? yy_state_stack_size * 2 : 16;
+        yy_state_stack = (int *)realloc(yy_state_stack, yy_state_stack_size * sizeof(int));
+        if (!yy_state_stack) {
+            fprintf(stderr, \"Out of memory growing start condition stack\\n\");
+            exit(1);
+        }
+    }
+    yy_state_stack[yy_state_stack_depth++] = yy_start;
+    BEGIN state;
+}
+
+// Go back to the start condition before the last yy_push_state()
+__attribute__((unused)) static void yy_pop_state(void) {
+    if (yy_state_stack_depth == 0) {
+        fprintf(stderr, \"Start condition stack underflow\\n\");
+        exit(1);
+    }
+    BEGIN yy_state_stack[--yy_state_stack_depth];
+}
+
+__attribute__((unused)) static int yy_top_state(void) {
+    return yy_state_stack_depth > 0 ? yy_state_stack[yy_state_stack_depth - 1] : yy_start;
+}
+";
+
+// Push a char back so that it is scanned next, unless %option nounput
+const UNPUT: &str = "\
+__attribute__((unused)) static void unput(int c) {
+    if (yy_current_pos == NULL || yy_current_pos == yy_buffer) {
+        // No room in front of the current position, shift the input up
+        size_t kept = yy_current_pos ? (size_t)(yy_buffer_end - yy_current_pos) : 0;
+        if (kept + 1 > yy_buffer_size) {
+            yy_buffer_size = yy_buffer_size ? yy_buffer_size * 2 : YY_BUFFER_SIZE;
+            yy_buffer = (char *)realloc(yy_buffer, yy_buffer_size);
+            if (!yy_buffer) {
+                fprintf(stderr, \"Out of memory in unput()\\n\");
+                exit(1);
+            }
+        }
+        if (kept > 0) {
+            memmove(yy_buffer + 1, yy_buffer, kept);
+        }
+        yy_current_pos = yy_buffer + 1;
+        yy_buffer_end = yy_buffer + 1 + kept;
+    }
+    *--yy_current_pos = (char)c;
+
+    // Assumes c is the char that was just read
+    if (c == '\\n') {
+        yylineno--;
+    } else if (yycolumn > 1) {
+        yycolumn--;
+    }
+}
+
+";
+
+// Same layout as bison's default location type
+const YYLTYPE: &str = "\
+#if !defined YYLTYPE && !defined YYLTYPE_IS_DECLARED
+typedef struct YYLTYPE {
+    int first_line;
+    int first_column;
+    int last_line;
+    int last_column;
+} YYLTYPE;
+#define YYLTYPE_IS_DECLARED 1
+";
+
+const ACCEPT_LOOKUP: &str = "static int is_accepting(StateID state) {\n    return yy_accept[state];\n}\n\n";
+
+// Smallest C integer type that holds all values
+fn c_int_type<'a>(values: impl Iterator<Item = &'a i32>) -> &'static str {
+    let (min, max) = values.fold((0, 0), |(min, max), &v| (v.min(min), v.max(max)));
+    if min >= i16::MIN as i32 && max <= i16::MAX as i32 {
+        "short"
+    } else {
+        "int"
+    }
+}
+
+fn c_array(name: &str, values: &[i32]) -> String {
+    let mut array = format!(
+        "static const {} {}[{}] = {{",
+        c_int_type(values.iter()),
+        name,
+        values.len().max(1)
+    );
+
+    for (i, value) in values.iter().enumerate() {
+        if i % 16 == 0 {
+            array.push_str("\n   ");
+        }
+        array.push_str(&format!(" {},", value));
+    }
+    if values.is_empty() {
+        array.push_str(" 0");
+    }
+
+    array.push_str("\n};\n\n");
+    array
+}
+
 // Helper function to get a readable description of a character
 fn char_description(ch: char) -> String {
     match ch {
@@ -410,6 +1127,16 @@ fn char_description(ch: char) -> String {
         '\t' => String::from("\\t (tab)"),
         ' ' => String::from("space"),
         '\x00'..='\x1F' | '\x7F' => format!("ASCII {:?} (control)", ch as u8),
+        // The scanner reads bytes, with --utf8 these are parts of encodings
+        '\u{80}'..='\u{FF}' => format!("byte 0x{:02X}", ch as u32),
         _ => format!("'{}'", ch),
     }
-}
\ No newline at end of file
+}
+fn class_description(chars: &ClassSet) -> String {
+    let shown: Vec<String> = chars.chars().take(3).map(char_description).collect();
+    if chars.len() > shown.len() {
+        format!("{}, ... ({} chars)", shown.join(", "), chars.len())
+    } else {
+        shown.join(", ")
+    }
+}
@@ -3,29 +3,31 @@ use std::convert::From;
 use std::default::Default;
 use std::fmt;
 
-use crate::{Action, StateID, TransitionSymbol, NFA};
+use crate::{Action, ClassSet, HeadLength, StateID, NFA};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DFA {
     pub states: BTreeSet<StateID>,
-    pub alphabet: BTreeSet<char>,
-    pub transitions: BTreeMap<(StateID, TransitionSymbol), StateID>,
+    pub alphabet: ClassSet,
+    // Equivalence classes of the chars in `alphabet` as intervals,
+    // start -> (end, class), see `NFA::equivalence_classes`. Class 0 holds
+    // every other char and never has a transition.
+    pub classes: BTreeMap<char, (char, usize)>,
+    pub transitions: BTreeMap<(StateID, usize), StateID>,
     pub start_state: StateID,
+    // Start states indexed by `2 * condition + at_beginning_of_line`,
+    // `start_state` is the first one
+    pub start_states: Vec<StateID>,
     pub final_states: BTreeSet<StateID>,
     pub actions: BTreeMap<StateID, Action>,
-}
-
-impl Default for DFA {
-    fn default() -> Self {
-        DFA {
-            states: BTreeSet::new(),
-            alphabet: BTreeSet::new(),
-            transitions: BTreeMap::new(),
-            start_state: 0,
-            final_states: BTreeSet::new(),
-            actions: BTreeMap::new(),
-        }
-    }
+    // Rule matched in each accepting state, see `NFA::rules`
+    pub rules: BTreeMap<StateID, usize>,
+    // Rules of lower priority that match the same text in an accepting
+    // state, in order, with their head length. REJECT falls back to them.
+    pub fallback_rules: BTreeMap<StateID, Vec<(usize, Option<HeadLength>)>>,
+    // Trailing context, see `NFA::head_lengths` and `NFA::head_ends`
+    pub head_lengths: BTreeMap<StateID, HeadLength>,
+    pub head_ends: BTreeMap<StateID, BTreeSet<usize>>,
 }
 
 impl fmt::Display for DFA {
@@ -34,16 +36,23 @@ impl fmt::Display for DFA {
 
         writeln!(f, "States: {:?}", self.states)?;
 
-        let alphabet: String = self.alphabet.iter().collect();
-        writeln!(f, "Alphabet: {}", alphabet)?;
+        writeln!(f, "Alphabet: {}", self.alphabet)?;
 
         writeln!(f, "Start StateID: {:?}", self.start_state)?;
 
+        writeln!(f, "Start StateIDs: {:?}", self.start_states)?;
+
         writeln!(f, "Finite States: {:?}", self.final_states)?;
 
+        writeln!(f, "Classes:")?;
+        for class in 1..self.class_count() {
+            let chars = self.class_set(class).to_string();
+            writeln!(f, "  {} = [{}]", class, chars.escape_debug())?;
+        }
+
         writeln!(f, "Transitions:")?;
-        for ((state, symbol), next_state) in &self.transitions {
-            writeln!(f, "  δ({:?}, {}) = {:?}", state, symbol, next_state)?;
+        for ((state, class), next_state) in &self.transitions {
+            writeln!(f, "  δ({:?}, {}) = {:?}", state, class, next_state)?;
         }
 
         writeln!(f, "Actions:")?;
@@ -57,40 +66,64 @@ impl fmt::Display for DFA {
 
 impl From<NFA> for DFA {
     fn from(nfa: NFA) -> DFA {
-        let mut dfa = DFA::default();
-        dfa.alphabet.extend(nfa.alphabet.iter());
+        DFA::from_start_states(&nfa, &[nfa.start_state])
+    }
+}
+
+impl DFA {
+    // Subset construction from several NFA start states at once, so that all
+    // start conditions share the states they have in common
+    pub fn from_start_states(nfa: &NFA, start_states: &[StateID]) -> DFA {
+        let mut dfa = DFA {
+            alphabet: nfa.alphabet.clone(),
+            classes: nfa.equivalence_classes(),
+            ..DFA::default()
+        };
+
+        // NFA transitions by class, a char class covers whole classes
+        let mut moves: BTreeMap<(StateID, usize), BTreeSet<StateID>> = BTreeMap::new();
+        for ((from, symbol), targets) in &nfa.transitions {
+            let mut classes = BTreeSet::new();
+            for &(start, end) in symbol.chars().ranges() {
+                classes.extend(dfa.classes.range(start..=end).map(|(_, &(_, class))| class));
+            }
+            for class in classes {
+                moves.entry((*from, class)).or_default().extend(targets);
+            }
+        }
 
-        let start_set = nfa.epsilon_closure(&BTreeSet::from([nfa.start_state]));
         let mut state_map = BTreeMap::new(); // Maps NFA state subsets to DFA StateIDs
         let mut dfa_state_counter = 0;
-
-        state_map.insert(start_set.clone(), dfa_state_counter);
-        dfa.states.insert(dfa_state_counter);
-        dfa.start_state = dfa_state_counter;
-
-        dfa_state_counter += 1;
         let mut queue = VecDeque::new();
-        queue.push_back(start_set);
+
+        for &start in start_states {
+            let start_set = nfa.epsilon_closure(&BTreeSet::from([start]));
+            let id = match state_map.get(&start_set) {
+                Some(&id) => id,
+                None => {
+                    let id = dfa_state_counter;
+                    dfa_state_counter += 1;
+                    state_map.insert(start_set.clone(), id);
+                    dfa.states.insert(id);
+                    dfa.mark_head_ends(id, nfa, &start_set);
+                    queue.push_back(start_set);
+                    id
+                }
+            };
+            dfa.start_states.push(id);
+        }
+        dfa.start_state = dfa.start_states.first().copied().unwrap_or_default();
 
         while let Some(current_nfa_states) = queue.pop_front() {
             let current_dfa_state = state_map[&current_nfa_states];
 
-            for &symbol in &dfa.alphabet {
+            for class in 1..dfa.class_count() {
                 let mut next_nfa_states = BTreeSet::new();
 
                 for &nfa_state in &current_nfa_states {
-                    if let Some(targets) = nfa.transitions.get(&(nfa_state, TransitionSymbol::Char(symbol))) {
+                    if let Some(targets) = moves.get(&(nfa_state, class)) {
                         next_nfa_states.extend(targets);
                     }
-                    for ((src, sym), targets) in &nfa.transitions {
-                        if *src == nfa_state {
-                            if let TransitionSymbol::CharClass(char_set) = sym {
-                                if char_set.contains(&symbol) {
-                                    next_nfa_states.extend(targets);
-                                }
-                            }
-                        }
-                    }
                 }
 
                 let next_nfa_states = nfa.epsilon_closure(&next_nfa_states);
@@ -105,23 +138,30 @@ impl From<NFA> for DFA {
                         dfa_state_counter += 1;
                         state_map.insert(next_nfa_states.clone(), new_id);
                         dfa.states.insert(new_id);
-
-                        let mut highest_priority_state: Option<StateID> = None;
-                        for &nfa_state in &next_nfa_states {
-                            if nfa.final_states.contains(&nfa_state) {
-                                if highest_priority_state.is_none()
-                                    || nfa_state < highest_priority_state.unwrap()
-                                {
-                                    highest_priority_state = Some(nfa_state);
-                                }
-                            }
-                        }
+                        dfa.mark_head_ends(new_id, nfa, &next_nfa_states);
+
+                        // The first listed rule wins, states without a rule
+                        // only win by their ID
+                        let highest_priority_state = next_nfa_states
+                            .iter()
+                            .filter(|nfa_state| nfa.final_states.contains(nfa_state))
+                            .min_by_key(|&&nfa_state| {
+                                (nfa.rules.get(&nfa_state).copied().unwrap_or(usize::MAX), nfa_state)
+                            })
+                            .copied();
 
                         if let Some(state) = highest_priority_state {
                             if let Some(action) = nfa.actions.get(&state) {
                                 dfa.final_states.insert(new_id);
                                 dfa.actions.insert(new_id, action.clone());
                             }
+                            if let Some(&rule) = nfa.rules.get(&state) {
+                                dfa.rules.insert(new_id, rule);
+                            }
+                            if let Some(&head_length) = nfa.head_lengths.get(&state) {
+                                dfa.head_lengths.insert(new_id, head_length);
+                            }
+                            dfa.mark_fallback_rules(new_id, nfa, &next_nfa_states);
                         }
 
                         queue.push_back(next_nfa_states.clone());
@@ -129,26 +169,77 @@ impl From<NFA> for DFA {
                     }
                 };
 
-                dfa.transitions
-                    .insert((current_dfa_state, TransitionSymbol::Char(symbol)), target_dfa_state);
+                dfa.transitions.insert((current_dfa_state, class), target_dfa_state);
             }
         }
 
         dfa
     }
-}
 
-impl DFA {
+    // Number of classes including class 0
+    pub fn class_count(&self) -> usize {
+        self.classes.values().map(|&(_, class)| class).max().map_or(1, |max| max + 1)
+    }
+
+    pub fn class_of(&self, c: char) -> usize {
+        match self.classes.range(..=c).next_back() {
+            Some((_, &(end, class))) if c <= end => class,
+            _ => 0,
+        }
+    }
+
+    pub fn class_set(&self, class: usize) -> ClassSet {
+        let ranges = self.classes.iter().filter(|(_, &(_, other))| other == class);
+        ClassSet::from_ranges(ranges.map(|(&start, &(end, _))| (start, end)).collect())
+    }
+
+    // Rule matched in an accepting state
+    pub fn rule_index(&self, state: StateID) -> Option<usize> {
+        self.rules.get(&state).copied()
+    }
+
+    pub fn next_state(&self, state: StateID, c: char) -> Option<StateID> {
+        self.transitions.get(&(state, self.class_of(c))).copied()
+    }
+
+    fn mark_head_ends(&mut self, state: StateID, nfa: &NFA, nfa_states: &BTreeSet<StateID>) {
+        let markers: BTreeSet<usize> = nfa_states
+            .iter()
+            .filter_map(|nfa_state| nfa.head_ends.get(nfa_state).copied())
+            .collect();
+
+        if !markers.is_empty() {
+            self.head_ends.insert(state, markers);
+        }
+    }
+
+    fn mark_fallback_rules(&mut self, state: StateID, nfa: &NFA, nfa_states: &BTreeSet<StateID>) {
+        let mut fallbacks: BTreeMap<usize, Option<HeadLength>> = BTreeMap::new();
+        for nfa_state in nfa_states.iter().filter(|nfa_state| nfa.final_states.contains(nfa_state)) {
+            if let Some(&rule) = nfa.rules.get(nfa_state) {
+                fallbacks.entry(rule).or_insert(nfa.head_lengths.get(nfa_state).copied());
+            }
+        }
+
+        // The first rule is the one the state accepts
+        let fallbacks: Vec<_> = fallbacks.into_iter().skip(1).collect();
+        if !fallbacks.is_empty() {
+            self.fallback_rules.insert(state, fallbacks);
+        }
+    }
+
     pub fn simulate(&self, input: &str) -> Vec<(String, Action)> {
         let mut tokens = Vec::new();
         let mut remaining = input.to_string();
+        let mut at_bol = true;
 
         while !remaining.is_empty() {
-            let (token, action, rest) = self.scan_next_token(&remaining);
+            let (token, action, rest) = self.scan_next_token(&remaining, self.initial_state(0, at_bol));
             if token.is_empty() {
                 break;
             }
 
+            at_bol = token.ends_with('\n');
             tokens.push((token, action));
             remaining = rest;
         }
@@ -156,23 +247,32 @@ impl DFA {
         tokens
     }
 
-    fn scan_next_token(&self, input: &str) -> (String, Action, String) {
-        let mut current_state = self.start_state;
+    // Start state for a start condition, depending on whether the previous
+    // char was a newline
+    pub fn initial_state(&self, condition: usize, at_bol: bool) -> StateID {
+        self.start_states
+            .get(2 * condition + at_bol as usize)
+            .copied()
+            .unwrap_or(self.start_state)
+    }
+
+    fn scan_next_token(&self, input: &str, start: StateID) -> (String, Action, String) {
+        let mut current_state = start;
         let mut last_accepting_state = None;
         let mut last_accepting_length = 0;
+        let mut head_positions = vec![None; self.head_marker_count()];
+
+        self.record_head_ends(current_state, 0, &mut head_positions);
 
         let chars: Vec<char> = input.chars().collect();
         for (i, &c) in chars.iter().enumerate() {
-            if !self.alphabet.contains(&c) {
-                break;
-            }
-
-            match self.transitions.get(&(current_state, TransitionSymbol::Char(c))) {
-                Some(&next_state) => {
+            match self.next_state(current_state, c) {
+                Some(next_state) => {
                     current_state = next_state;
+                    self.record_head_ends(current_state, i + 1, &mut head_positions);
                     if self.final_states.contains(&current_state) {
                         last_accepting_state = Some(current_state);
-                        last_accepting_length = i + 1;
+                        last_accepting_length = self.head_length(current_state, i + 1, &head_positions);
                     }
                 }
                 None => break,
@@ -187,17 +287,210 @@ impl DFA {
                     .get(&state)
                     .cloned()
                     .unwrap_or_else(|| "UNKNOWN".to_string());
-                let rest = input[last_accepting_length..].to_string();
+                let rest = input[token.len()..].to_string();
                 (token, action, rest)
             }
             None => (String::new(), String::new(), input.to_string()),
         }
     }
 
+    // Size of the buffer `record_head_ends` fills, one slot per marker
+    pub(crate) fn head_marker_count(&self) -> usize {
+        self.head_ends.values().flatten().max().map_or(0, |max| max + 1)
+    }
+
+    pub(crate) fn record_head_ends(&self, state: StateID, position: usize, positions: &mut [Option<usize>]) {
+        if let Some(markers) = self.head_ends.get(&state) {
+            for &marker in markers {
+                positions[marker] = Some(position);
+            }
+        }
+    }
+
+    // Length of the text to keep for a match of `length` chars ending in
+    // `state`, the rest is trailing context that gets rescanned
+    pub(crate) fn head_length(&self, state: StateID, length: usize, positions: &[Option<usize>]) -> usize {
+        match self.head_lengths.get(&state) {
+            Some(HeadLength::Fixed(head)) => *head,
+            Some(HeadLength::FromTail(tail)) => length - tail,
+            Some(HeadLength::Marker(marker)) => positions[*marker].unwrap_or(length),
+            None => length,
+        }
+    }
+
+    // Hopcroft's partition refinement. Accepting states start out grouped by
+    // action so states belonging to different rules are never merged.
     pub fn minimize(&self) -> DFA {
-        // Implementation of DFA minimization algorithm (Hopcroft's algorithm)
-        // This would reduce the number of states in the DFA
+        // Complete the automaton with an explicit dead state so every state
+        // has a transition on every symbol.
+        let dead = self.states.iter().max().map_or(0, |max| max + 1);
+        let mut states = self.states.clone();
+        states.insert(dead);
+
+        let symbols: Vec<usize> = (1..self.class_count()).collect();
+        let target = |state: StateID, class: usize| -> StateID {
+            self.transitions.get(&(state, class)).copied().unwrap_or(dead)
+        };
+
+        // Inverse transitions: (class, target) -> sources
+        let mut inverse: BTreeMap<(usize, StateID), Vec<StateID>> = BTreeMap::new();
+        for &state in &states {
+            for &symbol in &symbols {
+                inverse
+                    .entry((symbol, target(state, symbol)))
+                    .or_default()
+                    .push(state);
+            }
+        }
+
+        // States that accept differently, or carry different head-end
+        // markers, must never end up in the same block
+        let mut by_behavior: BTreeMap<_, BTreeSet<StateID>> = BTreeMap::new();
+        for &state in &states {
+            let action = self
+                .actions
+                .get(&state)
+                .filter(|_| self.final_states.contains(&state));
+            let key = (
+                action,
+                self.rules.get(&state),
+                self.fallback_rules.get(&state),
+                self.head_lengths.get(&state),
+                self.head_ends.get(&state),
+            );
+            by_behavior.entry(key).or_default().insert(state);
+        }
+        let mut partition: Vec<BTreeSet<StateID>> = by_behavior.into_values().collect();
+
+        let mut block_of: BTreeMap<StateID, usize> = BTreeMap::new();
+        for (index, block) in partition.iter().enumerate() {
+            for &state in block {
+                block_of.insert(state, index);
+            }
+        }
+
+        let mut worklist: BTreeSet<usize> = (0..partition.len()).collect();
+
+        while let Some(splitter) = worklist.pop_first() {
+            for &symbol in &symbols {
+                // States that reach the splitter block on this symbol
+                let mut predecessors = BTreeSet::new();
+                for &state in &partition[splitter] {
+                    if let Some(sources) = inverse.get(&(symbol, state)) {
+                        predecessors.extend(sources.iter().copied());
+                    }
+                }
+                if predecessors.is_empty() {
+                    continue;
+                }
+
+                let touched: BTreeSet<usize> =
+                    predecessors.iter().map(|state| block_of[state]).collect();
+
+                for block in touched {
+                    let (inside, outside): (BTreeSet<StateID>, BTreeSet<StateID>) = partition
+                        [block]
+                        .iter()
+                        .partition(|state| predecessors.contains(state));
+                    if outside.is_empty() {
+                        continue;
+                    }
+
+                    let new_block = partition.len();
+                    for &state in &outside {
+                        block_of.insert(state, new_block);
+                    }
+
+                    let smaller = if inside.len() <= outside.len() {
+                        block
+                    } else {
+                        new_block
+                    };
+
+                    partition[block] = inside;
+                    partition.push(outside);
+
+                    if worklist.contains(&block) {
+                        worklist.insert(new_block);
+                    } else {
+                        worklist.insert(smaller);
+                    }
+                }
+            }
+        }
+
+        // Renumber the surviving blocks in breadth-first order from the start
+        // states, dropping the block of the dead state.
+        let dead_block = block_of[&dead];
+        let mut minimized = DFA {
+            alphabet: self.alphabet.clone(),
+            classes: self.classes.clone(),
+            ..DFA::default()
+        };
+
+        let mut block_ids: BTreeMap<usize, StateID> = BTreeMap::new();
+        let mut queue = VecDeque::new();
+
+        for &start in &self.start_states {
+            let start_block = block_of[&start];
+            let id = match block_ids.get(&start_block) {
+                Some(&id) => id,
+                None => {
+                    let id = block_ids.len();
+                    block_ids.insert(start_block, id);
+                    minimized.states.insert(id);
+                    queue.push_back(start_block);
+                    id
+                }
+            };
+            minimized.start_states.push(id);
+        }
+        minimized.start_state = minimized.start_states.first().copied().unwrap_or_default();
+
+        while let Some(block) = queue.pop_front() {
+            let id = block_ids[&block];
+            let representative = *partition[block].first().unwrap();
+
+            if self.final_states.contains(&representative) {
+                minimized.final_states.insert(id);
+                if let Some(action) = self.actions.get(&representative) {
+                    minimized.actions.insert(id, action.clone());
+                }
+                if let Some(&rule) = self.rules.get(&representative) {
+                    minimized.rules.insert(id, rule);
+                }
+                if let Some(fallbacks) = self.fallback_rules.get(&representative) {
+                    minimized.fallback_rules.insert(id, fallbacks.clone());
+                }
+            }
+            if let Some(&head_length) = self.head_lengths.get(&representative) {
+                minimized.head_lengths.insert(id, head_length);
+            }
+            if let Some(markers) = self.head_ends.get(&representative) {
+                minimized.head_ends.insert(id, markers.clone());
+            }
+
+            for &symbol in &symbols {
+                let next_block = block_of[&target(representative, symbol)];
+                if next_block == dead_block {
+                    continue;
+                }
+
+                let next_id = match block_ids.get(&next_block) {
+                    Some(&next_id) => next_id,
+                    None => {
+                        let next_id = block_ids.len();
+                        block_ids.insert(next_block, next_id);
+                        minimized.states.insert(next_id);
+                        queue.push_back(next_block);
+                        next_id
+                    }
+                };
+
+                minimized.transitions.insert((id, symbol), next_id);
+            }
+        }
 
-        self.clone()
+        minimized
     }
 }
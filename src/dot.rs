@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use crate::{ClassSet, Regex, StateID, TransitionSymbol, DFA, NFA};
+
+// Graphviz output for debugging specs, render with `dot -Tsvg`
+
+impl Regex {
+    // The syntax tree, one node per AST node
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph Regex {\n");
+        dot.push_str("    node [shape=box, fontname=monospace];\n");
+        self.dot_node(&mut dot, &mut 0);
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Write this node and its children, returning the node's ID
+    fn dot_node(&self, dot: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+
+        let (label, children): (String, Vec<&Regex>) = match self {
+            Regex::Empty => ("Empty".to_string(), vec![]),
+            Regex::Char(c) => (format!("Char {}", char_label(*c)), vec![]),
+            Regex::CharClass(class) => (format!("Class [{}]", char_ranges(class.ranges())), vec![]),
+            Regex::NegatedCharClass(class) => (format!("Class [^{}]", char_ranges(class.ranges())), vec![]),
+            Regex::Dot => ("Dot".to_string(), vec![]),
+            Regex::StartAnchor => ("^".to_string(), vec![]),
+            Regex::EndAnchor => ("$".to_string(), vec![]),
+            Regex::TrailingContext(head, tail) => ("/".to_string(), vec![head, tail]),
+            Regex::IgnoreCase(inner, true) => ("(?i:)".to_string(), vec![inner]),
+            Regex::IgnoreCase(inner, false) => ("(?-i:)".to_string(), vec![inner]),
+            Regex::Union(left, right) => ("|".to_string(), vec![left, right]),
+            Regex::Concat(left, right) => ("Concat".to_string(), vec![left, right]),
+            Regex::Option(inner) => ("?".to_string(), vec![inner]),
+            Regex::Plus(inner) => ("+".to_string(), vec![inner]),
+            Regex::Kleene(inner) => ("*".to_string(), vec![inner]),
+            Regex::Bounded(inner, min, max) => {
+                let bounds = match max {
+                    Some(max) if max == min => format!("{{{}}}", min),
+                    Some(max) => format!("{{{},{}}}", min, max),
+                    None => format!("{{{},}}", min),
+                };
+                (bounds, vec![inner])
+            }
+        };
+
+        dot.push_str(&format!("    n{} [label=\"{}\"];\n", id, escape(&label)));
+        for child in children {
+            let child_id = child.dot_node(dot, next_id);
+            dot.push_str(&format!("    n{} -> n{};\n", id, child_id));
+        }
+
+        id
+    }
+}
+
+impl NFA {
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph NFA {\n");
+        dot.push_str("    rankdir=LR;\n");
+        dot.push_str("    node [shape=circle, fontname=monospace];\n");
+        dot.push_str("    edge [fontname=monospace];\n");
+
+        dot.push_str("    start [shape=point];\n");
+        dot.push_str(&format!("    start -> {};\n", self.start_state));
+
+        for &state in &self.final_states {
+            dot.push_str(&format!(
+                "    {} [shape=doublecircle, label=\"{}\"];\n",
+                state,
+                escape(&accepting_label(state, self.rules.get(&state)))
+            ));
+        }
+
+        let mut edges: BTreeMap<(StateID, StateID), ClassSet> = BTreeMap::new();
+        for ((from, symbol), targets) in &self.transitions {
+            for &to in targets {
+                if *symbol == TransitionSymbol::Epsilon {
+                    dot.push_str(&format!("    {} -> {} [label=\"ε\", style=dashed];\n", from, to));
+                } else {
+                    let chars = edges.entry((*from, to)).or_default();
+                    *chars = chars.union(&symbol.chars());
+                }
+            }
+        }
+        push_edges(&mut dot, &edges);
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl DFA {
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph DFA {\n");
+        dot.push_str("    rankdir=LR;\n");
+        dot.push_str("    node [shape=circle, fontname=monospace];\n");
+        dot.push_str("    edge [fontname=monospace];\n");
+
+        // Start states by start condition, `^` marks the beginning of a line
+        for (index, &state) in self.start_states.iter().enumerate() {
+            let label = format!("{}{}", index / 2, if index % 2 == 1 { "^" } else { "" });
+            dot.push_str(&format!("    start{} [shape=point];\n", index));
+            dot.push_str(&format!("    start{} -> {} [label=\"{}\"];\n", index, state, label));
+        }
+
+        for &state in &self.final_states {
+            dot.push_str(&format!(
+                "    {} [shape=doublecircle, label=\"{}\"];\n",
+                state,
+                escape(&accepting_label(state, self.rules.get(&state)))
+            ));
+        }
+
+        let mut edges: BTreeMap<(StateID, StateID), ClassSet> = BTreeMap::new();
+        for (&(from, class), &to) in &self.transitions {
+            let chars = edges.entry((from, to)).or_default();
+            *chars = chars.union(&self.class_set(class));
+        }
+        push_edges(&mut dot, &edges);
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn accepting_label(state: StateID, rule: Option<&usize>) -> String {
+    match rule {
+        Some(rule) => format!("{}\nrule {}", state, rule),
+        None => state.to_string(),
+    }
+}
+
+fn push_edges(dot: &mut String, edges: &BTreeMap<(StateID, StateID), ClassSet>) {
+    for ((from, to), chars) in edges {
+        dot.push_str(&format!(
+            "    {} -> {} [label=\"{}\"];\n",
+            from,
+            to,
+            escape(&char_set_label(chars))
+        ));
+    }
+}
+
+// Ranges of the set, or of its complement when that is shorter
+fn char_set_label(chars: &ClassSet) -> String {
+    if chars.len() == 1 {
+        return char_label(chars.ranges()[0].0);
+    }
+
+    // Scanners read bytes, complements are taken over all 256
+    let bytes = ClassSet::range('\0', '\u{FF}');
+    if chars.difference(&bytes).is_empty() && chars.len() > 128 {
+        let complement = bytes.difference(chars);
+        if complement.is_empty() {
+            return "any".to_string();
+        }
+        return format!("[^{}]", char_ranges(complement.ranges()));
+    }
+
+    format!("[{}]", char_ranges(chars.ranges()))
+}
+
+// Runs of three or more consecutive chars are written as `a-z`
+fn char_ranges(ranges: &[(char, char)]) -> String {
+    let mut label = String::new();
+
+    for &(first, last) in ranges {
+        label.push_str(&range_label(first));
+        if last as u32 > first as u32 + 1 {
+            label.push('-');
+        }
+        if last != first {
+            label.push_str(&range_label(last));
+        }
+    }
+
+    label
+}
+
+// Chars that mean something inside brackets get a backslash
+fn range_label(c: char) -> String {
+    match c {
+        '-' | ']' | '^' | '\\' => format!("\\{}", c),
+        _ => char_label(c),
+    }
+}
+
+fn char_label(c: char) -> String {
+    if c.is_ascii_graphic() {
+        c.to_string()
+    } else if c == ' ' {
+        "␣".to_string()
+    } else {
+        c.escape_default().to_string()
+    }
+}
+
+// Quote for a DOT string, `\n` in labels stays a line break
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
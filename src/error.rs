@@ -0,0 +1,197 @@
+use std::fmt;
+use std::io;
+
+// Position in a lex file, `line` and `column` are 1-based and `source` is
+// the text of that line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    // Generated code or debug dumps that can't be written
+    Write {
+        path: String,
+        source: io::Error,
+    },
+    // Misplaced `%%`, unclosed `%{` or action blocks, patterns without action
+    Section {
+        location: Location,
+        message: String,
+    },
+    // Malformed definitions and start condition declarations
+    Definition {
+        location: Location,
+        message: String,
+    },
+    // Rules that can't be split into pattern and action
+    Rule {
+        location: Location,
+        message: String,
+    },
+    // `offset` is the char offset inside `pattern`, `location` is only known
+    // for patterns that come from a lex file
+    Regex {
+        location: Option<Location>,
+        pattern: String,
+        offset: usize,
+        message: String,
+    },
+    UnknownMacro {
+        location: Location,
+        name: String,
+    },
+    // `cycle` starts and ends with the same definition
+    RecursiveMacro {
+        location: Location,
+        cycle: Vec<String>,
+    },
+}
+
+// Problems that don't stop the scanner from being generated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub location: Location,
+    pub message: String,
+}
+
+impl Error {
+    pub fn message(&self) -> String {
+        match self {
+            Error::Io { path, source } => format!("Failed to read file '{}': {}", path, source),
+            Error::Write { path, source } => format!("Failed to write file '{}': {}", path, source),
+            Error::Section { message, .. }
+            | Error::Definition { message, .. }
+            | Error::Rule { message, .. }
+            | Error::Regex { message, .. } => message.clone(),
+            Error::UnknownMacro { name, .. } => format!("Undefined macro '{{{}}}'", name),
+            Error::RecursiveMacro { cycle, .. } => {
+                format!("Recursive macro definition: {}", cycle.join(" -> "))
+            }
+        }
+    }
+
+    // Where the error points to. A regex outside of a lex file is shown as
+    // a file of its own.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Error::Io { .. } | Error::Write { .. } => None,
+            Error::Section { location, .. }
+            | Error::Definition { location, .. }
+            | Error::Rule { location, .. }
+            | Error::UnknownMacro { location, .. }
+            | Error::RecursiveMacro { location, .. } => Some(location.clone()),
+            Error::Regex {
+                location,
+                pattern,
+                offset,
+                ..
+            } => Some(location.clone().unwrap_or_else(|| Location {
+                file: "<pattern>".to_string(),
+                line: 1,
+                column: offset + 1,
+                source: pattern.clone(),
+            })),
+        }
+    }
+
+    // Diagnostic in the style of rustc:
+    //
+    //   error: Unterminated string literal
+    //    --> scanner.l:12:5
+    //     |
+    //  12 | "abc    { return X; }
+    //     |     ^
+    pub fn render(&self) -> String {
+        let mut output = render("error", &self.message(), self.location().as_ref());
+
+        if let Error::Regex {
+            location: Some(location),
+            pattern,
+            ..
+        } = self
+        {
+            if !location.source.contains(pattern.as_str()) {
+                let gutter = " ".repeat(location.line.to_string().len());
+                output.push_str(&format!("{} = note: in pattern '{}' after macro expansion\n", gutter, pattern));
+            }
+        }
+
+        output
+    }
+}
+
+impl Warning {
+    pub fn render(&self) -> String {
+        render("warning", &self.message, Some(&self.location))
+    }
+}
+
+fn render(level: &str, message: &str, location: Option<&Location>) -> String {
+    let mut output = format!("{}: {}\n", level, message);
+
+    let Some(location) = location else {
+        return output;
+    };
+
+    let number = location.line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    // Keep tabs so the caret lines up with the source line
+    let padding: String = location
+        .source
+        .chars()
+        .take(location.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    output.push_str(&format!("{}--> {}:{}:{}\n", gutter, location.file, location.line, location.column));
+    output.push_str(&format!("{} |\n", gutter));
+    output.push_str(&format!("{} | {}\n", number, location.source));
+    output.push_str(&format!("{} | {}^\n", gutter, padding));
+
+    output
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location() {
+            Some(location) => write!(
+                f,
+                "{}:{}:{}: {}",
+                location.file,
+                location.line,
+                location.column,
+                self.message()
+            ),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.location.file, self.location.line, self.location.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Write { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
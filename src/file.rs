@@ -1,6 +1,8 @@
-use std::{collections::BTreeMap, fs};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::ops::Range;
 
-use crate::{NFA, DFA, Regex};
+use crate::{Error, LexOptions, Location, NFA, DFA, Regex, StateID, Warning};
 
 pub enum LexSection {
     Definitions,
@@ -9,58 +11,204 @@ pub enum LexSection {
 }
 
 type Definitions = BTreeMap<String, String>;
+// Char range of each expanded `{NAME}` in a pattern, with the name
+type MacroSpans = Vec<(Range<usize>, String)>;
 
 pub struct Rule {
     pub pattern: String,
     pub nfa: NFA,
     pub action: String,
+    // Indices into `LexFile::start_conditions`, empty when the rule has no prefix
+    pub start_conditions: Vec<usize>,
+    // Pattern starts with `^`
+    pub start_anchored: bool,
+    // Where the pattern is written, unknown for rules not read from a file
+    pub location: Option<Location>,
+}
+
+// An `<<EOF>>` rule, its action runs when the input ends
+pub struct EofRule {
+    pub action: String,
+    // Empty when the rule has no prefix, it then covers every start
+    // condition without an `<<EOF>>` rule of its own
+    pub start_conditions: Vec<usize>,
+    pub location: Option<Location>,
 }
 
 pub struct PendingPattern {
     pub pattern: String,
     pub line_number: usize,
+    // Column where the pattern starts in its line
+    pub column: usize,
+    pub start_conditions: Vec<usize>,
+    // Where macros were expanded in `pattern`
+    pub macros: MacroSpans,
+}
+
+// A definition as written, expanded once the definitions section ends
+struct RawDefinition {
+    value: String,
+    name: Location,
+    value_column: usize,
+}
+
+pub struct StartCondition {
+    pub name: String,
+    pub exclusive: bool,
 }
 
 pub struct LexFile {
     pub definitions_code: Vec<String>,
     pub definitions: Definitions,
+    pub start_conditions: Vec<StartCondition>,
     pub rules: Vec<Rule>,
+    pub eof_rules: Vec<EofRule>,
     pub code: String,
+    pub options: LexOptions,
+    pub warnings: Vec<Warning>,
 }
 
 impl LexFile {
-    pub fn new(path: &str) -> Result<LexFile, String> {
-        let content = fs::read_to_string(path)
-            .map_err(|e| format!("Failed to read file '{}': {}", path, e))?;
+    pub fn new(path: &str) -> Result<LexFile, Error> {
+        let content = fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_string(),
+            source,
+        })?;
+
+        LexFile::parse(path, &content)
+    }
+
+    // Parse a spec that is already in memory, `name` is the file name used
+    // in diagnostics
+    pub fn parse(name: &str, content: &str) -> Result<LexFile, Error> {
         let lines: Vec<&str> = content.split('\n').collect();
 
-        let mut parser = LexParser::new(path, lines);
+        let mut parser = LexParser::new(name, lines);
         parser.parse()?;
 
-        Ok(LexFile {
+        let mut file = LexFile {
             definitions_code: parser.definitions_code,
             definitions: parser.definitions,
+            start_conditions: parser.start_conditions,
             rules: parser.rules,
+            eof_rules: parser.eof_rules,
             code: parser.code,
-        })
+            options: parser.options,
+            warnings: parser.warnings,
+        };
+        if file.options.case_insensitive || file.options.utf8 || !file.options.eight_bit {
+            file.compile_rules()?;
+        }
+
+        Ok(file)
     }
 
-    pub fn dfa(&self) -> Result<DFA, String> {
-        let mut combined_nfa = NFA::empty();
-        
+    // A file with only a rules section and no start conditions besides
+    // INITIAL
+    pub fn from_rules(rules: Vec<Rule>) -> LexFile {
+        LexFile {
+            definitions_code: Vec::new(),
+            definitions: BTreeMap::new(),
+            start_conditions: vec![StartCondition::initial()],
+            rules,
+            eof_rules: Vec::new(),
+            code: String::new(),
+            options: LexOptions::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    // Build a single NFA with two start states per start condition: start
+    // state `2 * condition + 1` is used at the beginning of a line and is the
+    // only one that also sees `^` rules
+    pub fn nfa(&self) -> (NFA, Vec<StateID>) {
+        let mut fragments = Vec::new();
+
         for rule in &self.rules {
-            let regex = Regex::new(&rule.pattern)
-                .map_err(|e| format!("Invalid pattern '{}': {}", rule.pattern, e))?;
-            
-            let mut fragment = NFA::from(regex);
+            let mut fragment = rule.nfa.clone();
             for state in fragment.final_states.clone() {
                 fragment.add_action(state, rule.action.clone());
             }
-            
-            combined_nfa = NFA::union(combined_nfa, fragment);
+
+            let mut starts = Vec::new();
+            for condition in 0..self.start_conditions.len() {
+                if self.is_active(rule, condition) {
+                    if !rule.start_anchored {
+                        starts.push(2 * condition);
+                    }
+                    starts.push(2 * condition + 1);
+                }
+            }
+
+            fragments.push((fragment, starts));
         }
 
-        Ok(DFA::from(combined_nfa))
+        NFA::with_start_states(2 * self.start_conditions.len(), fragments)
+    }
+
+    // The `<<EOF>>` rule for the end of input in a start condition
+    pub fn eof_rule(&self, condition: usize) -> Option<&EofRule> {
+        self.eof_rules
+            .iter()
+            .find(|rule| rule.start_conditions.contains(&condition))
+            .or_else(|| self.eof_rules.iter().find(|rule| rule.start_conditions.is_empty()))
+    }
+
+    // Rebuild the NFA of every rule after `options` changed, like `-i`
+    pub fn compile_rules(&mut self) -> Result<(), Error> {
+        for rule in &mut self.rules {
+            rule.compile(&self.options)?;
+        }
+        Ok(())
+    }
+
+    pub fn dfa(&self) -> Result<DFA, Error> {
+        let (nfa, start_states) = self.nfa();
+        let mut dfa = DFA::from_start_states(&nfa, &start_states);
+        // Without REJECT they would only keep states from being merged
+        if !self.uses_reject() {
+            dfa.fallback_rules.clear();
+        }
+
+        Ok(dfa)
+    }
+
+    // Some action can REJECT its match
+    pub fn uses_reject(&self) -> bool {
+        self.rules.iter().any(|rule| rule.action.contains("REJECT"))
+    }
+
+    // Rules that never win in an accepting state, because earlier rules
+    // match everything they match, and that REJECT can't fall back to
+    pub fn unmatchable_rules(&self, dfa: &DFA) -> Vec<usize> {
+        let matched: BTreeSet<usize> = dfa
+            .final_states
+            .iter()
+            .filter_map(|&state| dfa.rule_index(state))
+            .chain(dfa.fallback_rules.values().flatten().map(|&(rule, _)| rule))
+            .collect();
+
+        (0..self.rules.len()).filter(|rule| !matched.contains(rule)).collect()
+    }
+
+    pub fn unmatchable_rule_warnings(&self, dfa: &DFA) -> Vec<Warning> {
+        self.unmatchable_rules(dfa)
+            .into_iter()
+            .filter_map(|rule| self.rules[rule].location.clone())
+            .map(|location| Warning {
+                location,
+                message: "Rule cannot be matched".to_string(),
+            })
+            .collect()
+    }
+
+    // Unprefixed rules are active in every inclusive condition
+    fn is_active(&self, rule: &Rule, condition: usize) -> bool {
+        if rule.start_conditions.is_empty() {
+            !self.start_conditions[condition].exclusive
+        } else {
+            rule.start_conditions.contains(&condition)
+        }
     }
 }
 
@@ -69,8 +217,16 @@ struct LexParser<'a> {
     lines: Vec<&'a str>,
     definitions_code: Vec<String>,
     definitions: Definitions,
+    raw_definitions: BTreeMap<String, RawDefinition>,
+    // Definitions referenced by rules, and by each definition
+    used_definitions: BTreeSet<String>,
+    definition_references: BTreeMap<String, BTreeSet<String>>,
+    start_conditions: Vec<StartCondition>,
     rules: Vec<Rule>,
+    eof_rules: Vec<EofRule>,
     code: String,
+    options: LexOptions,
+    warnings: Vec<Warning>,
     pending_patterns: Vec<PendingPattern>,
     current_section: LexSection,
     line_index: usize,
@@ -83,17 +239,27 @@ impl<'a> LexParser<'a> {
             lines,
             definitions_code: Vec::new(),
             definitions: BTreeMap::new(),
+            raw_definitions: BTreeMap::new(),
+            used_definitions: BTreeSet::new(),
+            definition_references: BTreeMap::new(),
+            start_conditions: vec![StartCondition::initial()],
             rules: Vec::new(),
+            eof_rules: Vec::new(),
             code: String::new(),
+            options: LexOptions::default(),
+            warnings: Vec::new(),
             pending_patterns: Vec::new(),
             current_section: LexSection::Definitions,
             line_index: 0,
         }
     }
 
-    fn parse(&mut self) -> Result<(), String> {
+    fn parse(&mut self) -> Result<(), Error> {
         while self.line_index < self.lines.len() {
-            let line = self.lines[self.line_index].trim();
+            let source = self.lines[self.line_index];
+            let line = source.trim();
+            // Byte offset of `line` in the source line
+            let start = source.len() - source.trim_start().len();
             let line_number = self.line_index + 1;
 
             if line == "%%" {
@@ -108,25 +274,31 @@ impl<'a> LexParser<'a> {
             }
 
             match self.current_section {
-                LexSection::Definitions => self.process_definitions_line(line, line_number)?,
-                LexSection::Rules => self.process_rules_line(line, line_number)?,
+                LexSection::Definitions => self.process_definitions_line(line, start, line_number)?,
+                LexSection::Rules => self.process_rules_line(line, start, line_number)?,
                 LexSection::Code => self.process_code_line(),
             }
 
             self.line_index += 1;
         }
 
+        self.warn_unused_definitions();
         self.validate_final_state()
     }
 
-    fn handle_section_separator(&mut self) -> Result<(), String> {
+    fn handle_section_separator(&mut self) -> Result<(), Error> {
         match self.current_section {
-            LexSection::Definitions => self.current_section = LexSection::Rules,
+            LexSection::Definitions => {
+                self.expand_definitions()?;
+                self.current_section = LexSection::Rules;
+            }
             LexSection::Rules => self.current_section = LexSection::Code,
-            LexSection::Code => return Err(format!(
-                "Unexpected section separator at line {}",
-                self.line_index + 1
-            )),
+            LexSection::Code => {
+                return Err(Error::Section {
+                    location: self.location(self.line_index + 1, 1),
+                    message: "Unexpected section separator".to_string(),
+                })
+            }
         }
         Ok(())
     }
@@ -135,75 +307,205 @@ impl<'a> LexParser<'a> {
         line.is_empty() || line.starts_with("//") || line.starts_with('#')
     }
 
-    fn process_definitions_line(&mut self, line: &str, line_number: usize) -> Result<(), String> {
+    // `start` is the byte offset of `line` in the source line, the offsets
+    // passed on from here are too
+    fn process_definitions_line(&mut self, line: &str, start: usize, line_number: usize) -> Result<(), Error> {
         if line.starts_with("%{") {
             self.process_definitions_code_block()
+        } else if let Some(end) = line.find(char::is_whitespace) {
+            let (keyword, names) = (&line[..end], &line[end..]);
+            match keyword {
+                "%s" | "%start" | "%Start" => self.declare_start_conditions(names, start + end, false, line_number),
+                "%x" | "%exclusive" => self.declare_start_conditions(names, start + end, true, line_number),
+                "%option" | "%options" => self.process_options(names, start + end, line_number),
+                _ => self.process_definition(line, start, line_number),
+            }
         } else {
-            self.process_definition(line, line_number)
+            self.process_definition(line, start, line_number)
         }
     }
 
-    fn process_definitions_code_block(&mut self) -> Result<(), String> {
+    // `%option noyywrap prefix="calc"`, unknown options are only warned about
+    fn process_options(&mut self, options: &str, start: usize, line_number: usize) -> Result<(), Error> {
+        for (offset, option) in words(options) {
+            let location = self.location_at(line_number, start + offset);
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim_matches('"'))),
+                None => (option, None),
+            };
+
+            if !LexOptions::is_known(name) {
+                self.warnings.push(Warning {
+                    location,
+                    message: format!("Unknown option '{}' is ignored", name),
+                });
+                continue;
+            }
+
+            self.options.apply(name, value).map_err(|message| Error::Definition {
+                location: location.clone(),
+                message,
+            })?;
+
+            if name == "reentrant" {
+                self.warnings.push(Warning {
+                    location,
+                    message: "Reentrant scanners are not supported, the scanner uses global state".to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_definitions_code_block(&mut self) -> Result<(), Error> {
+        let line_number = self.line_index + 1;
         self.line_index += 1; // Skip opening %{
         
         while self.line_index < self.lines.len() {
             let line = self.lines[self.line_index];
             if line.trim().starts_with("%}") {
-                self.line_index += 1;
                 return Ok(());
             }
             self.definitions_code.push(line.to_string());
             self.line_index += 1;
         }
 
-        Err(format!("{}: Unclosed definitions code block", self.path))
+        Err(Error::Section {
+            location: self.location(line_number, 1),
+            message: "Unclosed definitions code block".to_string(),
+        })
     }
 
-    fn process_definition(&mut self, line: &str, line_number: usize) -> Result<(), String> {
-        let (name, value) = line.split_once(' ')
-            .ok_or_else(|| format!("{}:{}: Invalid definition format", self.path, line_number))?;
+    fn declare_start_conditions(
+        &mut self,
+        names: &str,
+        start: usize,
+        exclusive: bool,
+        line_number: usize,
+    ) -> Result<(), Error> {
+        for (offset, name) in words(names) {
+            if !Self::is_identifier(name) {
+                return Err(Error::Definition {
+                    location: self.location_at(line_number, start + offset),
+                    message: format!("Invalid start condition name '{}'", name),
+                });
+            }
+            if self.start_conditions.iter().any(|condition| condition.name == name) {
+                return Err(Error::Definition {
+                    location: self.location_at(line_number, start + offset),
+                    message: format!("Start condition '{}' declared twice", name),
+                });
+            }
+            self.start_conditions.push(StartCondition {
+                name: name.to_string(),
+                exclusive,
+            });
+        }
+        Ok(())
+    }
+
+    fn is_identifier(name: &str) -> bool {
+        let mut chars = name.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
 
-        let expanded_value = self.expand_macros(value.trim())?;
-        self.definitions.insert(name.trim().to_string(), expanded_value);
+    fn process_definition(&mut self, line: &str, start: usize, line_number: usize) -> Result<(), Error> {
+        let end = line.find(' ').ok_or_else(|| Error::Definition {
+            location: self.location_at(line_number, start),
+            message: "Invalid definition format".to_string(),
+        })?;
+
+        let (name, value) = (&line[..end], &line[end..]);
+        let value_start = start + end + value.len() - value.trim_start().len();
+        let definition = RawDefinition {
+            value: value.trim().to_string(),
+            name: self.location_at(line_number, start),
+            value_column: self.location_at(line_number, value_start).column,
+        };
+        self.raw_definitions.insert(name.trim().to_string(), definition);
         Ok(())
     }
 
-    fn process_rules_line(&mut self, line: &str, line_number: usize) -> Result<(), String> {
-        let (pattern, action) = Self::split_pattern_action(line)
-            .map_err(|e| format!("{}:{}: {}", self.path, line_number, e))?;
+    fn process_rules_line(&mut self, line: &str, start: usize, line_number: usize) -> Result<(), Error> {
+        let (start_conditions, rest) = self
+            .split_start_conditions(line)
+            .map_err(|message| Error::Rule {
+                location: self.location_at(line_number, start),
+                message,
+            })?;
+
+        // `rest` is what is left of `line` after the prefix
+        let column = self.location_at(line_number, start + line.len() - rest.len()).column;
+        let (pattern, action) = Self::split_pattern_action(rest).map_err(|message| Error::Rule {
+            location: self.location(line_number, column),
+            message,
+        })?;
+
+        let (expanded_pattern, macros) = self.expand_macros(&pattern, &self.location(line_number, column))?;
+        let pending = PendingPattern {
+            pattern: expanded_pattern,
+            line_number,
+            column,
+            start_conditions,
+            macros,
+        };
+        self.handle_rule_action(pending, action)
+    }
+
+    // Strip a `<A,B>` or `<*>` prefix from a rule, resolving the condition names
+    fn split_start_conditions<'l>(&self, line: &'l str) -> Result<(Vec<usize>, &'l str), String> {
+        let Some(rest) = line.strip_prefix('<') else {
+            return Ok((Vec::new(), line));
+        };
+        let Some((list, rest)) = rest.split_once('>') else {
+            return Ok((Vec::new(), line));
+        };
+
+        if list == "*" {
+            return Ok(((0..self.start_conditions.len()).collect(), rest));
+        }
+
+        let names: Vec<&str> = list.split(',').map(str::trim).collect();
+        if !names.iter().all(|name| Self::is_identifier(name)) {
+            return Ok((Vec::new(), line));
+        }
+
+        let mut conditions = Vec::new();
+        for name in names {
+            let index = self
+                .start_conditions
+                .iter()
+                .position(|condition| condition.name == name)
+                .ok_or_else(|| format!("Undeclared start condition '{}'", name))?;
+            if !conditions.contains(&index) {
+                conditions.push(index);
+            }
+        }
 
-        let expanded_pattern = self.expand_macros(&pattern)?;
-        self.handle_rule_action(expanded_pattern, action, line_number)
+        Ok((conditions, rest))
     }
 
-    fn handle_rule_action(
-        &mut self,
-        pattern: String,
-        action: String,
-        line_number: usize,
-    ) -> Result<(), String> {
+    fn handle_rule_action(&mut self, pending: PendingPattern, action: String) -> Result<(), Error> {
         if action == "|" {
-            self.pending_patterns.push(PendingPattern { pattern, line_number });
+            self.pending_patterns.push(pending);
             return Ok(());
         }
 
         if action.starts_with('{') {
-            self.process_action_block(pattern, action, line_number)
+            self.process_action_block(pending, action)
         } else {
-            self.commit_rule(pattern, action, line_number)
+            self.commit_rule(pending, action)
         }
     }
 
-    fn process_action_block(
-        &mut self,
-        pattern: String,
-        mut action: String,
-        line_number: usize,
-    ) -> Result<(), String> {
+    fn process_action_block(&mut self, pending: PendingPattern, mut action: String) -> Result<(), Error> {
+        let line_number = pending.line_number;
         let mut brace_count = action.chars().filter(|c| *c == '{').count() as i32;
         brace_count -= action.chars().filter(|c| *c == '}').count() as i32;
 
-        self.pending_patterns.push(PendingPattern { pattern, line_number });
+        self.pending_patterns.push(pending);
         let mut current_line = self.line_index;
 
         while brace_count > 0 && current_line < self.lines.len() - 1 {
@@ -217,68 +519,354 @@ impl<'a> LexParser<'a> {
         }
 
         if brace_count != 0 {
-            return Err(format!("{}: Unclosed action block starting at line {}", self.path, line_number));
+            return Err(Error::Section {
+                location: self.location(line_number, 1),
+                message: "Unclosed action block".to_string(),
+            });
         }
 
         self.line_index = current_line;
         self.commit_pending_rules(action)
     }
 
-    fn commit_pending_rules(&mut self, action: String) -> Result<(), String> {
-        for pending in self.pending_patterns.drain(..) {
-            self.rules.push(Rule::new(pending.pattern, action.clone())?);
+    fn commit_pending_rules(&mut self, action: String) -> Result<(), Error> {
+        for pending in std::mem::take(&mut self.pending_patterns) {
+            if pending.pattern == "<<EOF>>" {
+                self.commit_eof_rule(pending, action.clone())?;
+                continue;
+            }
+
+            let mut rule = Rule::new(pending.pattern.clone(), action.clone())
+                .map_err(|error| self.locate_regex_error(error, &pending))?;
+            rule.start_conditions = pending.start_conditions;
+            rule.location = Some(self.location(pending.line_number, pending.column));
+            if rule.matches_empty() {
+                self.warnings.push(Warning {
+                    location: self.location(pending.line_number, pending.column),
+                    message: "Rule can match the empty string, empty matches are skipped".to_string(),
+                });
+            }
+            self.rules.push(rule);
         }
         Ok(())
     }
 
-    fn commit_rule(&mut self, pattern: String, action: String, _line_number: usize) -> Result<(), String> {
-        if !self.pending_patterns.is_empty() {
-            self.commit_pending_rules(action.clone())?;
+    // At most one `<<EOF>>` rule per start condition, and one without prefix
+    fn commit_eof_rule(&mut self, pending: PendingPattern, action: String) -> Result<(), Error> {
+        let location = self.location(pending.line_number, pending.column);
+
+        let duplicate = self.eof_rules.iter().find_map(|rule| {
+            if pending.start_conditions.is_empty() {
+                rule.start_conditions.is_empty().then(|| "Multiple <<EOF>> rules without start condition".to_string())
+            } else {
+                pending
+                    .start_conditions
+                    .iter()
+                    .find(|condition| rule.start_conditions.contains(condition))
+                    .map(|&condition| {
+                        format!(
+                            "Multiple <<EOF>> rules for start condition '{}'",
+                            self.start_conditions[condition].name
+                        )
+                    })
+            }
+        });
+        if let Some(message) = duplicate {
+            return Err(Error::Rule { location, message });
         }
-        self.rules.push(Rule::new(pattern, action)?);
+
+        self.eof_rules.push(EofRule {
+            action,
+            start_conditions: pending.start_conditions,
+            location: Some(location),
+        });
         Ok(())
     }
 
+    fn commit_rule(&mut self, pending: PendingPattern, action: String) -> Result<(), Error> {
+        self.pending_patterns.push(pending);
+        self.commit_pending_rules(action)
+    }
+
     fn process_code_line(&mut self) {
         self.code.push_str(self.lines[self.line_index]);
         self.code.push('\n');
     }
 
-    fn expand_macros(&self, input: &str) -> Result<String, String> {
-        let mut result = input.to_string();
-        let mut changed = true;
+    // Expand `{NAME}` references in a pattern that starts at `location`,
+    // also returning where each expansion ended up
+    fn expand_macros(&mut self, input: &str, location: &Location) -> Result<(String, MacroSpans), Error> {
+        let mut result = String::new();
+        let mut macros = Vec::new();
+        let mut last = 0;
+
+        for (range, name) in macro_references(input) {
+            result.push_str(&input[last..range.start]);
+            let value = self.definitions.get(name).ok_or_else(|| Error::UnknownMacro {
+                location: Location {
+                    column: location.column + input[..range.start].chars().count(),
+                    ..location.clone()
+                },
+                name: name.to_string(),
+            })?;
+            let start = result.chars().count();
+            result.push_str(value);
+            macros.push((start..start + value.chars().count(), name.to_string()));
+            self.used_definitions.insert(name.to_string());
+            last = range.end;
+        }
+        result.push_str(&input[last..]);
 
-        while changed {
-            changed = false;
-            for (name, value) in &self.definitions {
-                let macro_ref = format!("{{{}}}", name);
-                if result.contains(&macro_ref) {
-                    result = result.replace(&macro_ref, value);
-                    changed = true;
-                }
+        Ok((result, macros))
+    }
+
+    // Definitions may refer to each other in any order, as long as there
+    // is no cycle
+    fn expand_definitions(&mut self) -> Result<(), Error> {
+        let mut expanded = BTreeMap::new();
+        let mut references = BTreeMap::new();
+
+        for name in self.raw_definitions.keys() {
+            self.expand_definition(name, &mut Vec::new(), &mut expanded, &mut references)?;
+        }
+
+        self.definitions = expanded;
+        self.definition_references = references;
+        Ok(())
+    }
+
+    fn expand_definition(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+        expanded: &mut Definitions,
+        references: &mut BTreeMap<String, BTreeSet<String>>,
+    ) -> Result<String, Error> {
+        if let Some(value) = expanded.get(name) {
+            return Ok(value.clone());
+        }
+
+        let definition = &self.raw_definitions[name];
+        stack.push(name.to_string());
+
+        let mut result = String::new();
+        let mut last = 0;
+        for (range, reference) in macro_references(&definition.value) {
+            result.push_str(&definition.value[last..range.start]);
+
+            let location = Location {
+                column: definition.value_column + definition.value[..range.start].chars().count(),
+                ..definition.name.clone()
+            };
+            if let Some(start) = stack.iter().position(|name| name == reference) {
+                let mut cycle = stack[start..].to_vec();
+                cycle.push(reference.to_string());
+                return Err(Error::RecursiveMacro { location, cycle });
+            }
+            if !self.raw_definitions.contains_key(reference) {
+                return Err(Error::UnknownMacro {
+                    location,
+                    name: reference.to_string(),
+                });
             }
+
+            references.entry(name.to_string()).or_default().insert(reference.to_string());
+            result.push_str(&self.expand_definition(reference, stack, expanded, references)?);
+            last = range.end;
         }
+        result.push_str(&definition.value[last..]);
 
+        stack.pop();
+        expanded.insert(name.to_string(), result.clone());
         Ok(result)
     }
 
-    fn validate_final_state(&self) -> Result<(), String> {
+    // A definition is used when a rule refers to it, directly or through
+    // other definitions
+    fn warn_unused_definitions(&mut self) {
+        let mut pending: Vec<String> = self.used_definitions.iter().cloned().collect();
+        while let Some(name) = pending.pop() {
+            for reference in self.definition_references.get(&name).into_iter().flatten() {
+                if self.used_definitions.insert(reference.clone()) {
+                    pending.push(reference.clone());
+                }
+            }
+        }
+
+        let mut unused: Vec<(&String, &RawDefinition)> = self
+            .raw_definitions
+            .iter()
+            .filter(|(name, _)| !self.used_definitions.contains(*name))
+            .collect();
+        unused.sort_by_key(|(_, definition)| definition.name.line);
+
+        for (name, definition) in unused {
+            self.warnings.push(Warning {
+                location: definition.name.clone(),
+                message: format!("Definition '{}' is never used", name),
+            });
+        }
+    }
+
+    fn validate_final_state(&self) -> Result<(), Error> {
         if !self.pending_patterns.is_empty() {
             let first_pending = &self.pending_patterns[0];
-            Err(format!(
-                "{}:{}: Pattern without action",
-                self.path, first_pending.line_number
-            ))
+            Err(Error::Section {
+                location: self.location(first_pending.line_number, first_pending.column),
+                message: "Pattern without action".to_string(),
+            })
         } else {
             Ok(())
         }
     }
 
+    fn location(&self, line_number: usize, column: usize) -> Location {
+        Location {
+            file: self.path.to_string(),
+            line: line_number,
+            column,
+            source: self.lines[line_number - 1].to_string(),
+        }
+    }
+
+    // Location of the char at byte `offset` in the line
+    fn location_at(&self, line_number: usize, offset: usize) -> Location {
+        let source = self.lines[line_number - 1];
+        self.location(line_number, source[..offset].chars().count() + 1)
+    }
+
+    // Regex errors only know the char offset inside the expanded pattern.
+    // Point into the definition when it falls inside an expanded macro, and
+    // into the rule otherwise.
+    fn locate_regex_error(&self, error: Error, pending: &PendingPattern) -> Error {
+        let Error::Regex { pattern, offset, message, .. } = error else {
+            return error;
+        };
+
+        let location = match pending.macros.iter().find(|(range, _)| range.contains(&offset)) {
+            Some((range, name)) => {
+                // Offsets inside a definition that refers to others can't be
+                // mapped back, point at the start of its value then
+                let definition = &self.raw_definitions[name];
+                let column = if self.definitions[name] == definition.value {
+                    definition.value_column + offset - range.start
+                } else {
+                    definition.value_column
+                };
+                Location {
+                    column,
+                    ..definition.name.clone()
+                }
+            }
+            None => {
+                // Undo the length change of the expansions before the error
+                let column = pending.macros.iter().filter(|(range, _)| range.end <= offset).fold(
+                    pending.column + offset,
+                    |column, (range, name)| column + name.chars().count() + 2 - range.len(),
+                );
+                self.location(pending.line_number, column)
+            }
+        };
+
+        Error::Regex {
+            location: Some(location),
+            pattern,
+            offset,
+            message,
+        }
+    }
+
     fn split_pattern_action(line: &str) -> Result<(String, String), String> {
         PatternParser::new().parse(line)
     }
 }
 
+// Words separated by whitespace, with their byte offset
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(word), true) => {
+                words.push((word, &text[word..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+// `{NAME}` references in a pattern, with their byte range. Braces inside
+// quotes, bracket expressions, `\u{...}` or after a backslash are not
+// references, and neither are repetitions like `{2,3}`.
+fn macro_references(pattern: &str) -> Vec<(Range<usize>, &str)> {
+    let chars: Vec<(usize, char)> = pattern.char_indices().collect();
+    let mut references = Vec::new();
+    let mut in_quote = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i].1 {
+            '\\' if chars.get(i + 1).map(|&(_, c)| c) == Some('u') => {
+                while i < chars.len() && chars[i].1 != '}' {
+                    i += 1;
+                }
+            }
+            '\\' => i += 1,
+            '"' => in_quote = !in_quote,
+            '[' if !in_quote => i = bracket_end(&chars, i),
+            '{' if !in_quote => {
+                let start = chars[i].0;
+                if let Some(length) = pattern[start + 1..].find('}') {
+                    let name = &pattern[start + 1..start + 1 + length];
+                    if LexParser::is_identifier(name) {
+                        references.push((start..start + length + 2, name));
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    references
+}
+
+// Index of the `]` closing the bracket expression that opens at `start`
+fn bracket_end(chars: &[(usize, char)], start: usize) -> usize {
+    let mut i = start + 1;
+
+    // A `]` right after `[` or `[^` is part of the set
+    if chars.get(i).map(|&(_, c)| c) == Some('^') {
+        i += 1;
+    }
+    if chars.get(i).map(|&(_, c)| c) == Some(']') {
+        i += 1;
+    }
+
+    while i < chars.len() {
+        match chars[i].1 {
+            '\\' => i += 1,
+            '[' if chars.get(i + 1).map(|&(_, c)| c) == Some(':') => {
+                // Skip `[:name:]`
+                while i + 1 < chars.len() && !(chars[i].1 == ':' && chars[i + 1].1 == ']') {
+                    i += 1;
+                }
+                i += 1;
+            }
+            ']' => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    i
+}
+
 struct PatternParser {
     in_bracket: i32,
     in_quote: bool,
@@ -303,7 +891,7 @@ impl PatternParser {
             match c {
                 '[' if !self.in_quote => self.in_bracket += 1,
                 ']' if !self.in_quote => self.in_bracket = (self.in_bracket - 1).max(0),
-                '"' => self.in_quote = !self.in_quote,
+                '"' if self.in_bracket == 0 => self.in_quote = !self.in_quote,
                 ' ' | '\t' if self.should_split() => {
                     self.split_pos = Some(i);
                     break;
@@ -315,12 +903,15 @@ impl PatternParser {
         self.split_result(line)
     }
 
+    // Returns true when `c` is a backslash or the char it escapes
     fn handle_escape(&mut self, c: char) -> bool {
-        if c == '\\' && !self.escaped {
+        if self.escaped {
+            self.escaped = false;
+            true
+        } else if c == '\\' {
             self.escaped = true;
             true
         } else {
-            self.escaped = false;
             false
         }
     }
@@ -346,10 +937,39 @@ impl PatternParser {
     }
 }
 
+impl StartCondition {
+    pub fn initial() -> Self {
+        StartCondition {
+            name: "INITIAL".to_string(),
+            exclusive: false,
+        }
+    }
+}
+
 impl Rule {
-    pub fn new(pattern: String, action: String) -> Result<Rule, String> {
-        let nfa = NFA::new(&pattern)
-            .map_err(|e| format!("Invalid regex pattern '{}': {}", pattern, e))?;
-        Ok(Rule { pattern, nfa, action })
+    pub fn new(pattern: String, action: String) -> Result<Rule, Error> {
+        let regex = Regex::new(&pattern)?;
+        let start_anchored = regex.is_start_anchored();
+        let nfa = NFA::from(regex);
+        Ok(Rule {
+            pattern,
+            nfa,
+            action,
+            start_conditions: Vec::new(),
+            start_anchored,
+            location: None,
+        })
+    }
+
+    // Rebuild the NFA with case folding, 7-bit and UTF-8 as set in `options`
+    pub fn compile(&mut self, options: &LexOptions) -> Result<(), Error> {
+        self.nfa = NFA::compile(Regex::new(&self.pattern)?, options);
+        Ok(())
+    }
+
+    // An accepting state is reachable without consuming input
+    pub fn matches_empty(&self) -> bool {
+        let closure = self.nfa.epsilon_closure(&BTreeSet::from([self.nfa.start_state]));
+        closure.iter().any(|state| self.nfa.final_states.contains(state))
     }
 }
\ No newline at end of file
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::{Error, LexFile, Rule, DFA};
+
+// What to do with input that no rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Recovery {
+    // Report the unmatched char and carry on after it
+    #[default]
+    Report,
+    // Drop unmatched chars without reporting them
+    Skip,
+    // Report the unmatched char and end the token stream
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    // Index of the matching rule in `LexFile::rules`
+    pub rule: usize,
+    pub text: &'a str,
+    // Byte offsets into the input
+    pub span: Range<usize>,
+    // 1-based position of the first char
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexError {
+    pub found: char,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: unexpected character {:?}", self.line, self.column, self.found)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+// Runs the scanner DFA directly instead of generating code for it. Actions
+// are not run, tokens only say which rule matched. Rules compiled without
+// UTF-8 mode read chars, but `.` and negated classes then stop at U+00FF,
+// so `from_patterns` always compiles in UTF-8 mode.
+pub struct Lexer {
+    dfa: DFA,
+    recovery: Recovery,
+    // The DFA reads the bytes of the UTF-8 encoding instead of chars
+    utf8: bool,
+}
+
+impl Lexer {
+    pub fn new(file: &LexFile) -> Result<Lexer, Error> {
+        Ok(Lexer {
+            dfa: file.dfa()?.minimize(),
+            recovery: Recovery::default(),
+            utf8: file.options.utf8,
+        })
+    }
+
+    // One rule per pattern, in order, compiled in UTF-8 mode
+    pub fn from_patterns(patterns: &[&str]) -> Result<Lexer, Error> {
+        let rules = patterns
+            .iter()
+            .map(|pattern| Rule::new(pattern.to_string(), String::new()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut file = LexFile::from_rules(rules);
+        file.options.utf8 = true;
+        file.compile_rules()?;
+        Lexer::new(&file)
+    }
+
+    pub fn with_recovery(mut self, recovery: Recovery) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    pub fn tokens<'l, 'a>(&'l self, input: &'a str) -> Tokens<'l, 'a> {
+        Tokens {
+            lexer: self,
+            input,
+            pos: 0,
+            line: 1,
+            column: 1,
+            start_condition: 0,
+            at_bol: true,
+            done: false,
+            offsets: Vec::new(),
+            head_positions: vec![None; self.dfa.head_marker_count()],
+        }
+    }
+}
+
+pub struct Tokens<'l, 'a> {
+    lexer: &'l Lexer,
+    input: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+    start_condition: usize,
+    at_bol: bool,
+    done: bool,
+    // Byte offset after each symbol of the current match and where each
+    // trailing context marker was last seen, kept between tokens to avoid
+    // allocating
+    offsets: Vec<usize>,
+    head_positions: Vec<Option<usize>>,
+}
+
+impl<'l, 'a> Tokens<'l, 'a> {
+    // Switch to an index of `LexFile::start_conditions`, like BEGIN
+    pub fn begin(&mut self, condition: usize) {
+        self.start_condition = condition;
+    }
+
+    pub fn start_condition(&self) -> usize {
+        self.start_condition
+    }
+
+    // Longest match from the current position: (rule, length in bytes)
+    fn longest_match(&mut self) -> Option<(usize, usize)> {
+        let rest = &self.input[self.pos..];
+        if self.lexer.utf8 {
+            self.longest_match_in(rest.bytes().enumerate().map(|(i, byte)| (i + 1, byte as char)))
+        } else {
+            self.longest_match_in(rest.char_indices().map(|(offset, c)| (offset + c.len_utf8(), c)))
+        }
+    }
+
+    // `symbols` are the symbols read by the DFA with the offset where each
+    // ends in the rest of the input
+    fn longest_match_in(&mut self, symbols: impl Iterator<Item = (usize, char)>) -> Option<(usize, usize)> {
+        let dfa = &self.lexer.dfa;
+        let mut state = dfa.initial_state(self.start_condition, self.at_bol);
+        let mut matched = None;
+
+        self.offsets.clear();
+        self.offsets.push(self.pos);
+        self.head_positions.fill(None);
+        dfa.record_head_ends(state, 0, &mut self.head_positions);
+
+        for (i, (end, c)) in symbols.enumerate() {
+            match dfa.next_state(state, c) {
+                Some(next) => state = next,
+                None => break,
+            }
+            self.offsets.push(self.pos + end);
+            dfa.record_head_ends(state, i + 1, &mut self.head_positions);
+
+            if !dfa.final_states.contains(&state) {
+                continue;
+            }
+            if let Some(&rule) = dfa.rules.get(&state) {
+                // A match that keeps nothing would never make progress
+                let length = dfa.head_length(state, i + 1, &self.head_positions);
+                if length > 0 {
+                    matched = Some((rule, length));
+                }
+            }
+        }
+
+        matched.map(|(rule, length)| (rule, self.offsets[length] - self.pos))
+    }
+
+    fn advance(&mut self, length: usize) {
+        let text = &self.input[self.pos..self.pos + length];
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+
+        self.at_bol = text.ends_with('\n');
+        self.pos += length;
+    }
+}
+
+impl<'l, 'a> Iterator for Tokens<'l, 'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done && self.pos < self.input.len() {
+            let (start, line, column) = (self.pos, self.line, self.column);
+
+            let Some((rule, length)) = self.longest_match() else {
+                let found = self.input[start..].chars().next().unwrap();
+                self.advance(found.len_utf8());
+
+                let error = LexError {
+                    found,
+                    offset: start,
+                    line,
+                    column,
+                };
+                match self.lexer.recovery {
+                    Recovery::Report => return Some(Err(error)),
+                    Recovery::Skip => continue,
+                    Recovery::Stop => {
+                        self.done = true;
+                        return Some(Err(error));
+                    }
+                }
+            };
+
+            // Trailing context is not consumed and gets scanned again
+            self.advance(length);
+
+            return Some(Ok(Token {
+                rule,
+                text: &self.input[start..self.pos],
+                span: start..self.pos,
+                line,
+                column,
+            }));
+        }
+
+        None
+    }
+}
@@ -1,18 +1,33 @@
 pub mod args;
+pub mod class_set;
 pub mod code;
 pub mod dfa;
+pub mod dot;
+pub mod error;
 pub mod file;
+pub mod lexer;
 pub mod nfa;
+pub mod options;
 pub mod regex;
+pub mod rust;
+pub mod tables;
 pub mod transition;
+pub mod utf8;
 
 pub use args::*;
+pub use class_set::*;
 pub use code::*;
 pub use dfa::*;
+pub use error::*;
 pub use file::*;
+pub use lexer::*;
 pub use nfa::*;
+pub use options::*;
 pub use regex::*;
+pub use rust::*;
+pub use tables::*;
 pub use transition::*;
+pub use utf8::*;
 
 pub type StateID = usize;
 pub type Action = String;
@@ -1,41 +1,156 @@
-use std::fs::File;
+use std::fs;
+use std::io;
+use std::io::Read;
 use std::io::Write;
-use std::io::stdout;
+use std::path::Path;
+use std::process::ExitCode;
 
+use lex::Args;
+use lex::ArgsParser;
 use lex::CodeGenerator;
+use lex::Error;
+use lex::Language;
 use lex::LexFile;
-use lex::ArgsParser;
+use lex::RustGenerator;
+use lex::USAGE;
 
-fn main() -> Result<(), String> {
-    let parser = ArgsParser::new();
+fn main() -> ExitCode {
+    let args = match ArgsParser::new().parse() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("lex: {}", message);
+            eprintln!("Try 'lex --help' for more information.");
+            return ExitCode::from(2);
+        }
+    };
 
-    if parser.args().len() < 2 {
-        return Err("usage: program [options] file".to_string());
+    if args.help {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    if args.version {
+        println!("lex {}", env!("CARGO_PKG_VERSION"));
+        return ExitCode::SUCCESS;
     }
-    
-    // let language = parser.get_argument("--language", "c");
-    // println!("language: {}", language);
 
-    let mut output: Box<dyn Write> = if !parser.has_flag("-t") {
-        let filename = "lex.yy.c";
-        let file = File::create(filename);
-        match file {
-            Ok(file) => Box::new(file),
-            Err(e) => return Err(format!("{e}")),
+    match run(&args) {
+        Ok(code) => code,
+        Err(error) => {
+            eprint!("{}", error.render());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<ExitCode, Error> {
+    let mut file = match &args.input {
+        Some(path) => LexFile::new(path)?,
+        None => {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content).map_err(|source| Error::Io {
+                path: "<stdin>".to_string(),
+                source,
+            })?;
+            LexFile::parse("<stdin>", &content)?
         }
-    } else {
-        Box::new(stdout())
     };
-    
-    let input = parser.get_file();
+    // The command line overrides %option
+    if (args.case_insensitive && !file.options.case_insensitive) || (args.utf8 && !file.options.utf8) {
+        file.options.case_insensitive |= args.case_insensitive;
+        file.options.utf8 |= args.utf8;
+        file.compile_rules()?;
+    }
+    file.options.debug |= args.debug;
+    for (option, value) in [
+        (&mut file.options.prefix, &args.prefix),
+        (&mut file.options.outfile, &args.outfile),
+        (&mut file.options.header_file, &args.header_file),
+    ] {
+        if value.is_some() {
+            option.clone_from(value);
+        }
+    }
 
-    let file = LexFile::new(&input)?;
     let dfa = file.dfa()?;
+    let states_before_minimization = dfa.states.len();
+    let dfa = dfa.minimize();
+
+    if args.verbose {
+        let (nfa, _) = file.nfa();
+        eprintln!(
+            "lex: {} rules, {} start conditions",
+            file.rules.len(),
+            file.start_conditions.len()
+        );
+        eprintln!(
+            "lex: {} NFA states, {} DFA states ({} before minimization), {} equivalence classes",
+            nfa.states.len(),
+            dfa.states.len(),
+            states_before_minimization,
+            dfa.class_count() - 1
+        );
+    }
+
+    // Graphviz dumps for debugging, named after the input file
+    let stem = args
+        .input
+        .as_deref()
+        .and_then(|input| Path::new(input).file_stem())
+        .map_or("lex".into(), |stem| stem.to_string_lossy());
+    if args.dump_nfa {
+        let (nfa, _) = file.nfa();
+        write_file(&format!("{}.nfa.dot", stem), &nfa.to_dot())?;
+    }
+    if args.dump_dfa {
+        write_file(&format!("{}.dfa.dot", stem), &dfa.to_dot())?;
+    }
+
+    let mut warnings = file.warnings.clone();
+    warnings.extend(file.unmatchable_rule_warnings(&dfa));
+    for warning in &warnings {
+        eprint!("{}", warning.render());
+    }
+    if args.werror && !warnings.is_empty() {
+        eprintln!("error: aborting due to {} warning(s) with --Werror", warnings.len());
+        return Ok(ExitCode::FAILURE);
+    }
 
-    let generator = CodeGenerator::new(file, dfa);
-    let code = generator.code();
+    let outfile = file.options.outfile.clone();
+    let code = match args.language {
+        Language::C => {
+            let header_file = file.options.header_file.clone();
+            let generator = CodeGenerator::new(file, dfa, args.backend);
+            if let Some(path) = header_file {
+                write_file(&path, &generator.header())?;
+            }
+            generator.code()
+        }
+        Language::Rust => RustGenerator::new(file, dfa)?.code(),
+    };
 
-    writeln!(output, "{}", code).map_err(|e| format!("{}", e))?;
+    // Only touch the output once the scanner was generated
+    if args.stdout {
+        let result = writeln!(io::stdout(), "{}", code);
+        // A closed pipe, like `lex -t spec.l | head`, is not an error
+        if let Err(source) = result.and_then(|_| io::stdout().flush()) {
+            if source.kind() != io::ErrorKind::BrokenPipe {
+                return Err(Error::Write {
+                    path: "<stdout>".to_string(),
+                    source,
+                });
+            }
+        }
+    } else {
+        let path = outfile.as_deref().unwrap_or(args.default_outfile());
+        write_file(path, &format!("{}\n", code))?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
 
-    Ok(())
+fn write_file(path: &str, content: &str) -> Result<(), Error> {
+    fs::write(path, content).map_err(|source| Error::Write {
+        path: path.to_string(),
+        source,
+    })
 }
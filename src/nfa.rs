@@ -3,18 +3,35 @@ use std::default::Default;
 use std::fmt;
 
 use crate::Action;
+use crate::Error;
+use crate::LexOptions;
 use crate::Regex;
 use crate::StateID;
 use crate::TransitionSymbol;
+use crate::{next_char, previous_char, ClassSet};
+
+// How to recover the length of `r` once `r/s` has matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HeadLength {
+    Fixed(usize),     // r always matches this many chars
+    FromTail(usize),  // s always matches this many chars
+    Marker(usize),    // last position where head-end marker was seen
+}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct NFA {
     pub states: BTreeSet<StateID>,
-    pub alphabet: BTreeSet<char>,
+    pub alphabet: ClassSet,
     pub transitions: BTreeMap<(StateID, TransitionSymbol), BTreeSet<StateID>>,
     pub start_state: StateID,
     pub final_states: BTreeSet<StateID>,
     pub actions: BTreeMap<StateID, Action>,
+    // Trailing context: head length of accepting states, and the states
+    // where a head ends mapped to their marker
+    pub head_lengths: BTreeMap<StateID, HeadLength>,
+    pub head_ends: BTreeMap<StateID, usize>,
+    // Index of the rule each accepting state belongs to
+    pub rules: BTreeMap<StateID, usize>,
 }
 
 impl fmt::Display for NFA {
@@ -23,8 +40,7 @@ impl fmt::Display for NFA {
 
         writeln!(f, "States: {:?}", self.states)?;
 
-        let alphabet: String = self.alphabet.iter().collect();
-        writeln!(f, "Alphabet: {}", alphabet)?;
+        writeln!(f, "Alphabet: {}", self.alphabet)?;
 
         writeln!(f, "Start StateID: {:?}", self.start_state)?;
 
@@ -45,47 +61,84 @@ impl fmt::Display for NFA {
     }
 }
 
-impl Default for NFA {
-    fn default() -> NFA {
-        NFA {
-            states: BTreeSet::new(),
-            alphabet: BTreeSet::new(),
-            transitions: BTreeMap::new(),
-            start_state: 0,
-            final_states: BTreeSet::new(),
-            actions: BTreeMap::new(),
-        }
+impl From<Regex> for NFA {
+    fn from(regex: Regex) -> NFA {
+        NFA::compile(regex, &LexOptions::default())
     }
 }
 
-impl From<Regex> for NFA {
-    fn from(regex: Regex) -> NFA {
+impl NFA {
+    pub fn new(string: &str) -> Result<NFA, Error> {
+        let nfa = NFA::from(Regex::new(string)?);
+
+        Ok(nfa)
+    }
+
+    // The NFA of a regex with case folding, 7-bit and UTF-8 as set in
+    // `options`. These only change how chars and classes are matched, and
+    // folding has to happen before a negated class is resolved.
+    pub fn compile(regex: Regex, options: &LexOptions) -> NFA {
+        NFA::from_regex(regex, options, options.case_insensitive)
+    }
+
+    fn from_regex(regex: Regex, options: &LexOptions, fold: bool) -> NFA {
+        let from = |regex: Regex| NFA::from_regex(regex, options, fold);
+        let fold_case = |class: ClassSet| if fold { class.fold_case() } else { class };
+        // Everything a negated class can match
+        let all = if !options.eight_bit {
+            ClassSet::range('\0', '\x7F')
+        } else if options.utf8 {
+            ClassSet::full()
+        } else {
+            ClassSet::range('\0', '\u{FF}')
+        };
+
         match regex {
             Regex::Empty => NFA::empty(),
+            Regex::Char(c) if (fold && c.is_ascii_alphabetic()) || (options.utf8 && !c.is_ascii()) => {
+                NFA::class(fold_case(ClassSet::from(c)), options)
+            }
             Regex::Char(c) => NFA::char(c),
-            Regex::CharClass(class) => NFA::char_class(class),
-            Regex::NegatedCharClass(class) => NFA::negated_char_class(class),
-            Regex::Dot => NFA::dot(),
-
-            Regex::Concat(left, right) => NFA::concat(NFA::from(*left), NFA::from(*right)),
-            Regex::Union(left, right) => NFA::union(NFA::from(*left), NFA::from(*right)),
-            Regex::Kleene(inner) => NFA::kleene(NFA::from(*inner)),
-            Regex::Option(inner) => NFA::optional(NFA::from(*inner)),
-            Regex::Plus(inner) => NFA::plus(NFA::from(*inner)),
-            Regex::Bounded(inner, min, max) => NFA::bounded(NFA::from(*inner), min, max),
-
-            _ => panic!("Not implemented"),
+            Regex::CharClass(class) => NFA::class(fold_case(class), options),
+            Regex::NegatedCharClass(class) => NFA::class(all.difference(&fold_case(class)), options),
+            Regex::Dot => NFA::class(all.difference(&ClassSet::from('\n')), options),
+            Regex::IgnoreCase(inner, fold) => NFA::from_regex(*inner, options, fold),
+            // Beginning of line is handled by the choice of start state
+            Regex::StartAnchor => NFA::empty(),
+            Regex::EndAnchor => NFA::char('\n'),
+
+            Regex::Concat(left, right) => NFA::concat(from(*left), from(*right)),
+            Regex::Union(left, right) => NFA::union(from(*left), from(*right)),
+            Regex::Kleene(inner) => NFA::kleene(from(*inner)),
+            Regex::Option(inner) => NFA::optional(from(*inner)),
+            Regex::Plus(inner) => NFA::plus(from(*inner)),
+            Regex::Bounded(inner, min, max) => NFA::bounded(from(*inner), min, max),
+            Regex::TrailingContext(head, tail) => {
+                // Lengths count the symbols the DFA reads, bytes in UTF-8 mode
+                let fixed_length = |regex: &Regex| {
+                    if options.utf8 {
+                        regex.fixed_utf8_length()
+                    } else {
+                        regex.fixed_length()
+                    }
+                };
+                let head_length = match (fixed_length(&head), fixed_length(&tail)) {
+                    (Some(length), _) => HeadLength::Fixed(length),
+                    (None, Some(length)) => HeadLength::FromTail(length),
+                    (None, None) => HeadLength::Marker(0),
+                };
+                NFA::trailing_context(from(*head), from(*tail), head_length)
+            }
         }
     }
-}
-
-impl NFA {
-    pub fn new(string: &String) -> Result<NFA, String> {
-        let nfa = NFA::from(
-            Regex::new(string).map_err(|e| format!("{} : {}", string, e))?
-        );
 
-        Ok(nfa)
+    // A class of chars, or in UTF-8 mode of their encodings as bytes
+    fn class(class: ClassSet, options: &LexOptions) -> NFA {
+        if options.utf8 {
+            NFA::from(Regex::utf8_class(&class))
+        } else {
+            NFA::char_class(class)
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -111,20 +164,10 @@ impl NFA {
     fn add_transition(&mut self, from: StateID, symbol: TransitionSymbol, to: StateID) {
         self.transitions
             .entry((from, symbol.clone()))
-            .or_insert(BTreeSet::new())
+            .or_default()
             .insert(to);
 
-        match symbol {
-            TransitionSymbol::Epsilon => {}
-            TransitionSymbol::Char(c) => {
-                self.alphabet.insert(c);
-            }
-            TransitionSymbol::CharClass(class) => {
-                for &c in &class {
-                    self.alphabet.insert(c);
-                }
-            }
-        }
+        self.alphabet = self.alphabet.union(&symbol.chars());
     }
 
     pub fn empty() -> NFA {
@@ -151,29 +194,18 @@ impl NFA {
         nfa
     }
 
-    pub fn char_class(chars: BTreeSet<char>) -> NFA {
+    pub fn char_class(class: ClassSet) -> NFA {
         let mut nfa = NFA::default();
         let start = nfa.add_state();
         let end = nfa.add_state();
 
         nfa.start_state = start;
         nfa.final_states.insert(end);
-        nfa.add_transition(start, TransitionSymbol::CharClass(chars), end);
+        nfa.add_transition(start, TransitionSymbol::CharClass(class), end);
 
         nfa
     }
 
-    pub fn negated_char_class(class: BTreeSet<char>) -> NFA {
-        let mut negated = BTreeSet::new();
-        for c in (0..128).map(|i| i as u8 as char) {
-            if !class.contains(&c) {
-                negated.insert(c);
-            }
-        }
-
-        NFA::char_class(negated)
-    }
-
     pub fn concat_multiples(nfas: Vec<NFA>) -> NFA {
         match nfas.len() {
             0 => NFA::empty(),
@@ -181,7 +213,7 @@ impl NFA {
             _ => {
                 let mut iter = nfas.into_iter();
                 let first = iter.next().unwrap();
-                iter.fold(first, |acc, nfa| NFA::concat(acc, nfa))
+                iter.fold(first, NFA::concat)
             }
         }
     }
@@ -196,6 +228,9 @@ impl NFA {
             if let Some(action) = first.actions.get(&state) {
                 nfa.actions.insert(new_state, action.clone());
             }
+            if let Some(&marker) = first.head_ends.get(&state) {
+                nfa.head_ends.insert(new_state, marker);
+            }
         }
 
         let mut second_map = BTreeMap::new();
@@ -236,8 +271,71 @@ impl NFA {
             }
         }
 
-        nfa.alphabet.extend(first.alphabet.iter());
-        nfa.alphabet.extend(second.alphabet.iter());
+        nfa.alphabet = first.alphabet.union(&second.alphabet);
+
+        nfa
+    }
+
+    // Combine rule fragments under `count` start states. Each fragment is
+    // reachable from the start states listed alongside it; fragments keep
+    // their relative order so earlier ones get lower state IDs. Head-end
+    // markers are renumbered to the index of their fragment, which is also
+    // recorded as the rule of its accepting states.
+    pub fn with_start_states(count: usize, fragments: Vec<(NFA, Vec<usize>)>) -> (NFA, Vec<StateID>) {
+        let mut nfa = NFA::default();
+        let start_states: Vec<StateID> = (0..count).map(|_| nfa.add_state()).collect();
+
+        for (index, (fragment, starts)) in fragments.into_iter().enumerate() {
+            let mut map = BTreeMap::new();
+            for &state in &fragment.states {
+                let new_state = nfa.add_state();
+                map.insert(state, new_state);
+                if let Some(action) = fragment.actions.get(&state) {
+                    nfa.actions.insert(new_state, action.clone());
+                }
+                if fragment.head_ends.contains_key(&state) {
+                    nfa.head_ends.insert(new_state, index);
+                }
+                if let Some(&head_length) = fragment.head_lengths.get(&state) {
+                    let head_length = match head_length {
+                        HeadLength::Marker(_) => HeadLength::Marker(index),
+                        head_length => head_length,
+                    };
+                    nfa.head_lengths.insert(new_state, head_length);
+                }
+            }
+
+            for ((from, symbol), to_states) in &fragment.transitions {
+                for &to in to_states {
+                    nfa.add_transition(map[from], symbol.clone(), map[&to]);
+                }
+            }
+
+            for &final_state in &fragment.final_states {
+                nfa.final_states.insert(map[&final_state]);
+                nfa.rules.insert(map[&final_state], index);
+            }
+
+            for start in starts {
+                nfa.add_transition(start_states[start], TransitionSymbol::Epsilon, map[&fragment.start_state]);
+            }
+        }
+
+        nfa.start_state = start_states.first().copied().unwrap_or_default();
+
+        (nfa, start_states)
+    }
+
+    // Concatenation that remembers where the head ends
+    pub fn trailing_context(mut head: NFA, tail: NFA, head_length: HeadLength) -> NFA {
+        for state in head.final_states.clone() {
+            head.head_ends.insert(state, 0);
+        }
+
+        let mut nfa = NFA::concat(head, tail);
+        for state in nfa.final_states.clone() {
+            nfa.head_lengths.insert(state, head_length);
+        }
 
         nfa
     }
@@ -249,7 +347,7 @@ impl NFA {
             _ => {
                 let mut iter = nfas.into_iter();
                 let first = iter.next().unwrap();
-                iter.fold(first, |acc, nfa| NFA::union(acc, nfa))
+                iter.fold(first, NFA::union)
             }
         }
     }
@@ -298,8 +396,7 @@ impl NFA {
             nfa.final_states.insert(second_map[&final_state]);
         }
 
-        nfa.alphabet.extend(first.alphabet.iter());
-        nfa.alphabet.extend(second.alphabet.iter());
+        nfa.alphabet = first.alphabet.union(&second.alphabet);
 
         nfa
     }
@@ -333,7 +430,7 @@ impl NFA {
             nfa.add_transition(map[&finite], TransitionSymbol::Epsilon, end);
         }
 
-        nfa.alphabet.extend(inner.alphabet.iter());
+        nfa.alphabet = nfa.alphabet.union(&inner.alphabet);
 
         nfa
     }
@@ -373,20 +470,11 @@ impl NFA {
             nfa.add_transition(map[&finite], TransitionSymbol::Epsilon, end);
         }
 
-        nfa.alphabet.extend(inner.alphabet.iter());
+        nfa.alphabet = nfa.alphabet.union(&inner.alphabet);
 
         nfa
     }
 
-    pub fn dot() -> NFA {
-        let mut chars = BTreeSet::new();
-        for c in 0..128u8 {
-            chars.insert(c as char);
-        }
-
-        NFA::char_class(chars)
-    }
-
     pub fn bounded(inner: NFA, min: usize, max: Option<usize>) -> NFA {
         if min == 0 && max.is_none() {
             return NFA::kleene(inner);
@@ -427,4 +515,37 @@ impl NFA {
 
         closure
     }
+
+    // Split the alphabet into classes of chars that no transition tells
+    // apart. Classes are numbered from 1 in order of their smallest char and
+    // returned as intervals: start -> (end, class).
+    pub fn equivalence_classes(&self) -> BTreeMap<char, (char, usize)> {
+        let symbols: BTreeSet<&TransitionSymbol> = self.transitions.keys().map(|(_, symbol)| symbol).collect();
+        let sets: Vec<ClassSet> = symbols.into_iter().map(TransitionSymbol::chars).collect();
+
+        // No transition starts or stops between two boundaries, so each
+        // interval between them is in a set either completely or not at all.
+        // The end of the chars is 0x110000.
+        let mut boundaries = BTreeSet::new();
+        for &(start, end) in sets.iter().flat_map(|set| set.ranges()) {
+            boundaries.insert(start as u32);
+            boundaries.insert(next_char(end).map_or(0x110000, u32::from));
+        }
+
+        let mut class_ids: BTreeMap<Vec<usize>, usize> = BTreeMap::new();
+        let mut classes = BTreeMap::new();
+        let mut boundaries = boundaries.into_iter().peekable();
+        while let Some(start) = boundaries.next().and_then(char::from_u32) {
+            let signature: Vec<usize> = (0..sets.len()).filter(|&index| sets[index].contains(start)).collect();
+            if signature.is_empty() {
+                continue;
+            }
+
+            let end = boundaries.peek().and_then(|&next| char::from_u32(next)).map_or(char::MAX, previous_char);
+            let next_id = class_ids.len() + 1;
+            classes.insert(start, (end, *class_ids.entry(signature).or_insert(next_id)));
+        }
+
+        classes
+    }
 }
@@ -0,0 +1,124 @@
+// Settings from `%option` lines, also set from the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexOptions {
+    // Call yywrap() at the end of input, `noyywrap` acts as if it returned 1
+    pub yywrap: bool,
+    // Accepted for flex compatibility, yylineno is always maintained
+    pub yylineno: bool,
+    pub case_insensitive: bool,
+    // `.` and negated classes match all 256 bytes, `%option 7bit` limits
+    // them to ASCII
+    pub eight_bit: bool,
+    // Patterns match UTF-8 encoded code points instead of single bytes
+    pub utf8: bool,
+    // Replaces `yy` in exported names
+    pub prefix: Option<String>,
+    pub outfile: Option<String>,
+    pub header_file: Option<String>,
+    // Accepted for flex compatibility, the scanner is never reentrant
+    pub reentrant: bool,
+    // Generate input() and unput()
+    pub input: bool,
+    pub unput: bool,
+    // Generate yy_push_state(), yy_pop_state() and yy_top_state()
+    pub stack: bool,
+    // Report matched rules on stderr while yy_flex_debug is set
+    pub debug: bool,
+}
+
+impl Default for LexOptions {
+    fn default() -> Self {
+        LexOptions {
+            yywrap: true,
+            yylineno: false,
+            case_insensitive: false,
+            eight_bit: true,
+            utf8: false,
+            prefix: None,
+            outfile: None,
+            header_file: None,
+            reentrant: false,
+            input: true,
+            unput: true,
+            stack: false,
+            debug: false,
+        }
+    }
+}
+
+impl LexOptions {
+    // Whether `name` is an option, flags may have a `no` prefix
+    pub fn is_known(name: &str) -> bool {
+        let flag = name.strip_prefix("no").unwrap_or(name);
+        matches!(
+            flag,
+            "yywrap"
+                | "yylineno"
+                | "case-insensitive"
+                | "caseless"
+                | "case-sensitive"
+                | "7bit"
+                | "8bit"
+                | "utf8"
+                | "reentrant"
+                | "input"
+                | "unput"
+                | "stack"
+                | "debug"
+        ) || matches!(name, "prefix" | "outfile" | "header-file")
+    }
+
+    // Set one option, `value` is what follows `=` without quotes
+    pub fn apply(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let target = match name {
+            "prefix" => &mut self.prefix,
+            "outfile" => &mut self.outfile,
+            "header-file" => &mut self.header_file,
+            _ => return self.apply_flag(name, value),
+        };
+
+        match value {
+            Some(value) if !value.is_empty() => {
+                *target = Some(value.to_string());
+                Ok(())
+            }
+            _ => Err(format!("Option '{}' requires a value", name)),
+        }
+    }
+
+    fn apply_flag(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        if value.is_some() {
+            return Err(format!("Option '{}' doesn't take a value", name));
+        }
+
+        let (flag, enabled) = match name.strip_prefix("no") {
+            Some(flag) => (flag, false),
+            None => (name, true),
+        };
+
+        let target = match flag {
+            "yywrap" => &mut self.yywrap,
+            "yylineno" => &mut self.yylineno,
+            "case-insensitive" | "caseless" => &mut self.case_insensitive,
+            "case-sensitive" => {
+                self.case_insensitive = !enabled;
+                return Ok(());
+            }
+            "8bit" => &mut self.eight_bit,
+            "7bit" => {
+                self.eight_bit = !enabled;
+                return Ok(());
+            }
+            "utf8" => &mut self.utf8,
+            "reentrant" => &mut self.reentrant,
+            "input" => &mut self.input,
+            "unput" => &mut self.unput,
+            "stack" => &mut self.stack,
+            "debug" => &mut self.debug,
+            _ => return Err(format!("Unknown option '{}'", name)),
+        };
+        *target = enabled;
+
+        Ok(())
+    }
+}
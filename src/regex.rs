@@ -1,14 +1,19 @@
-use std::collections::BTreeSet;
 use std::fmt;
 
+use crate::utf8_sequences;
+use crate::ClassSet;
+use crate::Error;
+
 pub enum Regex {
     Empty,
     Char(char),
-    CharClass(BTreeSet<char>),
-    NegatedCharClass(BTreeSet<char>),
-    Dot,
-    StartAnchor, // ^ at start of regex
-    EndAnchor,   // $ at end of regex
+    CharClass(ClassSet),
+    NegatedCharClass(ClassSet),
+    Dot, // any char but newline, `(?s:.)` is `NegatedCharClass` of nothing
+    StartAnchor, // ^ at start of regex, matches the empty string at the beginning of a line
+    EndAnchor,   // $ at end of regex, the newline in the trailing context
+    TrailingContext(Box<Regex>, Box<Regex>), // r/s: match r only when followed by s
+    IgnoreCase(Box<Regex>, bool),            // (?i:r) or (?-i:r): letters in r match both cases or not
 
     Union(Box<Regex>, Box<Regex>),
     Concat(Box<Regex>, Box<Regex>),
@@ -25,9 +30,14 @@ impl fmt::Display for Regex {
 }
 
 impl Regex {
-    pub fn new(regex: &str) -> Result<Regex, String> {
+    pub fn new(regex: &str) -> Result<Regex, Error> {
         let mut parser = RegexParser::new(regex);
-        parser.parse()
+        parser.parse().map_err(|message| Error::Regex {
+            location: None,
+            pattern: regex.to_string(),
+            offset: parser.pos.min(parser.chars.len()),
+            message,
+        })
     }
 
     fn fmt(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
@@ -37,19 +47,11 @@ impl Regex {
             Regex::Char(c) => {
                 write!(f, "{}Char('{}')", indent_str, c)?;
             }
-            Regex::CharClass(chars) => {
-                write!(f, "{}CharClass[", indent_str)?;
-                for c in chars {
-                    write!(f, "{}", c)?;
-                }
-                write!(f, "]")?;
+            Regex::CharClass(class) => {
+                write!(f, "{}CharClass[{}]", indent_str, class)?;
             }
-            Regex::NegatedCharClass(chars) => {
-                write!(f, "{}NegatedCharClass[^", indent_str)?;
-                for c in chars {
-                    write!(f, "{}", c)?;
-                }
-                write!(f, "]")?;
+            Regex::NegatedCharClass(class) => {
+                write!(f, "{}NegatedCharClass[^{}]", indent_str, class)?;
             }
             Regex::Dot => {
                 write!(f, "{}Dot", indent_str)?;
@@ -102,14 +104,105 @@ impl Regex {
                 right.fmt(f, indent + 2)?;
                 write!(f, "\n{}}}", indent_str)?;
             }
+            Regex::TrailingContext(head, tail) => {
+                writeln!(f, "{}TrailingContext {{", indent_str)?;
+                head.fmt(f, indent + 2)?;
+                writeln!(f, ",")?;
+                tail.fmt(f, indent + 2)?;
+                write!(f, "\n{}}}", indent_str)?;
+            }
+            Regex::IgnoreCase(inner, fold) => {
+                writeln!(f, "{}IgnoreCase({}) {{", indent_str, fold)?;
+                inner.fmt(f, indent + 2)?;
+                write!(f, "\n{}}}", indent_str)?;
+            }
         }
         Ok(())
     }
+
+    // Whether the regex only matches at the beginning of a line
+    pub fn is_start_anchored(&self) -> bool {
+        match self {
+            Regex::StartAnchor => true,
+            Regex::Concat(left, _) | Regex::TrailingContext(left, _) => left.is_start_anchored(),
+            _ => false,
+        }
+    }
+
+    // Number of chars matched by the regex, if every match has the same length
+    pub fn fixed_length(&self) -> Option<usize> {
+        self.fixed_length_in(false)
+    }
+
+    // The same in bytes of the UTF-8 encoding, which is what the DFA reads
+    // in UTF-8 mode. `.` and negated classes match chars of any length.
+    pub fn fixed_utf8_length(&self) -> Option<usize> {
+        self.fixed_length_in(true)
+    }
+
+    fn fixed_length_in(&self, utf8: bool) -> Option<usize> {
+        let fixed_length = |regex: &Regex| regex.fixed_length_in(utf8);
+
+        match self {
+            Regex::Empty | Regex::StartAnchor => Some(0),
+            Regex::Char(c) if utf8 => Some(c.len_utf8()),
+            // The encoded length only grows with the code point
+            Regex::CharClass(class) if utf8 => {
+                let &(first, _) = class.ranges().first()?;
+                let &(_, last) = class.ranges().last()?;
+                (first.len_utf8() == last.len_utf8()).then_some(first.len_utf8())
+            }
+            Regex::NegatedCharClass(_) | Regex::Dot if utf8 => None,
+            Regex::Char(_)
+            | Regex::CharClass(_)
+            | Regex::NegatedCharClass(_)
+            | Regex::Dot
+            | Regex::EndAnchor => Some(1),
+            Regex::Concat(left, right) => Some(fixed_length(left)? + fixed_length(right)?),
+            Regex::Union(left, right) => {
+                let length = fixed_length(left)?;
+                (fixed_length(right)? == length).then_some(length)
+            }
+            Regex::Option(inner) | Regex::Plus(inner) | Regex::Kleene(inner) => {
+                fixed_length(inner).filter(|&length| length == 0)
+            }
+            Regex::Bounded(inner, min, max) if Some(*min) == *max => Some(fixed_length(inner)? * min),
+            Regex::Bounded(inner, _, _) => fixed_length(inner).filter(|&length| length == 0),
+            Regex::IgnoreCase(inner, _) => fixed_length(inner),
+            Regex::TrailingContext(..) => None,
+        }
+    }
+
+    // A class as a union of sequences of byte classes, the chars below 256
+    // of its UTF-8 encodings, so `.` and negated classes match whole code
+    // points
+    pub(crate) fn utf8_class(class: &ClassSet) -> Regex {
+        let byte_class = |(start, end): (u8, u8)| Regex::CharClass(ClassSet::range(char::from(start), char::from(end)));
+
+        class
+            .ranges()
+            .iter()
+            .flat_map(|&(start, end)| utf8_sequences(start, end))
+            .map(|sequence| {
+                let bytes = sequence.into_iter().map(byte_class);
+                bytes.reduce(|left, right| Regex::Concat(Box::new(left), Box::new(right))).unwrap()
+            })
+            .reduce(|left, right| Regex::Union(Box::new(left), Box::new(right)))
+            // An empty class matches nothing, like the empty class it was
+            .unwrap_or(Regex::CharClass(ClassSet::new()))
+    }
 }
 
+// `\d`, `\w` and `\s`, the negated escapes match everything else
+const DIGIT_RANGES: [(char, char); 1] = [('0', '9')];
+const WORD_RANGES: [(char, char); 4] = [('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE_RANGES: [(char, char); 2] = [('\t', '\r'), (' ', ' ')];
+
 pub struct RegexParser {
     chars: Vec<char>,
     pos: usize,
+    // Inside `(?s:...)`, where `.` also matches newline
+    dot_all: bool,
 }
 
 impl RegexParser {
@@ -117,6 +210,7 @@ impl RegexParser {
         RegexParser {
             chars: regex.chars().collect(),
             pos: 0,
+            dot_all: false,
         }
     }
 
@@ -172,21 +266,34 @@ impl RegexParser {
 
         let mut expr = self.parse_union()?;
 
+        if self.current_char() == Some('/') {
+            self.advance();
+            let tail = self.parse_union()?;
+            expr = Regex::TrailingContext(Box::new(expr), Box::new(tail));
+        }
+
+        // `$` is trailing context on a newline
         if self.current_char() == Some('$') {
+            if matches!(expr, Regex::TrailingContext(..)) {
+                return Err("'$' cannot be used together with trailing context".to_string());
+            }
             self.advance();
-            expr = Regex::Concat(Box::new(expr), Box::new(Regex::EndAnchor));
+            expr = Regex::TrailingContext(Box::new(expr), Box::new(Regex::EndAnchor));
         }
 
+        // `^` stays in front of the head so the trailing context remains outermost
         if start_anchored {
-            expr = Regex::Concat(Box::new(Regex::StartAnchor), Box::new(expr));
+            expr = match expr {
+                Regex::TrailingContext(head, tail) => Regex::TrailingContext(
+                    Box::new(Regex::Concat(Box::new(Regex::StartAnchor), head)),
+                    tail,
+                ),
+                expr => Regex::Concat(Box::new(Regex::StartAnchor), Box::new(expr)),
+            };
         }
 
         if !self.at_end() {
-            return Err(format!(
-                "Unexpected char '{}' at {}",
-                self.current_char().unwrap(),
-                self.pos
-            ));
+            return Err(format!("Unexpected char '{}'", self.current_char().unwrap()));
         }
         Ok(expr)
     }
@@ -204,7 +311,7 @@ impl RegexParser {
     fn parse_concat(&mut self) -> Result<Regex, String> {
         let mut factors = Vec::new();
         while let Some(c) = self.current_char() {
-            if c == ')' || c == '|' || c == '$' {
+            if c == ')' || c == '|' || c == '$' || c == '/' {
                 break;
             }
             factors.push(self.parse_operator()?);
@@ -222,7 +329,7 @@ impl RegexParser {
     }
 
     fn parse_operator(&mut self) -> Result<Regex, String> {
-        let mut expr = self.parse_base()?;
+        let mut expr = self.parse_class_operators()?;
 
         if let Some(c) = self.current_char() {
             match c {
@@ -248,6 +355,50 @@ impl RegexParser {
         Ok(expr)
     }
 
+    // Flex's `[a-z]{-}[aeiou]` and `[a-z]{+}[_]`, left associative. A negated
+    // class is kept as the set it excludes, so no universe has to be picked
+    // before `%option 7bit` or UTF-8 mode are known.
+    fn parse_class_operators(&mut self) -> Result<Regex, String> {
+        let mut expr = self.parse_base()?;
+
+        loop {
+            let union = if self.match_string("{-}") {
+                false
+            } else if self.match_string("{+}") {
+                true
+            } else {
+                return Ok(expr);
+            };
+            let operator = if union { "{+}" } else { "{-}" };
+
+            let left = Self::class_operand(expr, operator)?;
+            if self.at_end() {
+                return Err(format!("Expected a character class after '{}'", operator));
+            }
+            let right = Self::class_operand(self.parse_base()?, operator)?;
+
+            expr = match (left, right, union) {
+                ((false, a), (false, b), false) => Regex::CharClass(a.difference(&b)),
+                ((false, a), (true, b), false) => Regex::CharClass(a.intersection(&b)),
+                ((true, a), (false, b), false) => Regex::NegatedCharClass(a.union(&b)),
+                ((true, a), (true, b), false) => Regex::CharClass(b.difference(&a)),
+                ((false, a), (false, b), true) => Regex::CharClass(a.union(&b)),
+                ((false, a), (true, b), true) => Regex::NegatedCharClass(b.difference(&a)),
+                ((true, a), (false, b), true) => Regex::NegatedCharClass(a.difference(&b)),
+                ((true, a), (true, b), true) => Regex::NegatedCharClass(a.intersection(&b)),
+            };
+        }
+    }
+
+    // Whether the class is negated, and its set
+    fn class_operand(expr: Regex, operator: &str) -> Result<(bool, ClassSet), String> {
+        match expr {
+            Regex::CharClass(class) => Ok((false, class)),
+            Regex::NegatedCharClass(class) => Ok((true, class)),
+            _ => Err(format!("The '{}' operator needs a character class on both sides", operator)),
+        }
+    }
+
     fn parse_bounded(&mut self, expr: Regex) -> Result<Regex, String> {
         if self.current_char() != Some('{') {
             return Ok(expr);
@@ -267,7 +418,7 @@ impl RegexParser {
         }
 
         if self.current_char() != Some('}') {
-            return Err(format!("Expected '}}' at position {}", self.pos));
+            return Err("Expected '}' to close repetition".to_string());
         }
         self.advance();
 
@@ -293,7 +444,11 @@ impl RegexParser {
             Some('[') => self.parse_char_class(),
             Some('.') => {
                 self.advance();
-                Ok(Regex::Dot)
+                Ok(if self.dot_all {
+                    Regex::NegatedCharClass(ClassSet::new())
+                } else {
+                    Regex::Dot
+                })
             }
             Some('\\') => self.parse_escape(),
             Some('"') => self.parse_literal(),
@@ -310,21 +465,48 @@ impl RegexParser {
 
     fn parse_group(&mut self) -> Result<Regex, String> {
         self.advance();
-        let _ = self.check_non_capturing_group();
-        let expr = self.parse_union()?;
+        let (fold, dot_all) = self.parse_group_flags()?;
+
+        let outer_dot_all = self.dot_all;
+        self.dot_all = dot_all.unwrap_or(outer_dot_all);
+        let expr = self.parse_union();
+        self.dot_all = outer_dot_all;
+        let expr = expr?;
+
+        if self.current_char() == Some('/') {
+            return Err("Trailing context '/' is only allowed at the top level of a pattern".to_string());
+        }
         if self.current_char() != Some(')') {
             return Err("Unmatched parenthesis".to_string());
         }
         self.advance();
-        Ok(expr)
+
+        Ok(match fold {
+            Some(fold) => Regex::IgnoreCase(Box::new(expr), fold),
+            None => expr,
+        })
     }
 
-    fn check_non_capturing_group(&mut self) -> bool {
-        if self.current_char() == Some('?') && self.peek(1) == Some(':') {
-            self.pos += 2;
-            true
-        } else {
-            false
+    // `(?:`, or with flex's flags like `(?i:`, `(?-i:` or `(?s-i:`. Returns
+    // whether the group ignores case and whether its `.` matches newline,
+    // `None` when the group keeps the setting around it.
+    fn parse_group_flags(&mut self) -> Result<(Option<bool>, Option<bool>), String> {
+        if self.current_char() != Some('?') {
+            return Ok((None, None));
+        }
+        self.advance();
+
+        let (mut fold, mut dot_all) = (None, None);
+        let mut enabled = true;
+        loop {
+            match self.consume_char() {
+                Some(':') => return Ok((fold, dot_all)),
+                Some('-') if enabled => enabled = false,
+                Some('i') => fold = Some(enabled),
+                Some('s') => dot_all = Some(enabled),
+                Some(c) => return Err(format!("Unknown group flag '{}'", c)),
+                None => return Err("Expected ':' after group flags".to_string()),
+            }
         }
     }
 
@@ -381,7 +563,7 @@ impl RegexParser {
 
     fn parse_char_class(&mut self) -> Result<Regex, String> {
         self.advance();
-        let mut chars = BTreeSet::new();
+        let mut ranges = Vec::new();
         let mut negated = false;
 
         if self.current_char() == Some('^') {
@@ -399,32 +581,22 @@ impl RegexParser {
             }
 
             if c == '[' && self.peek(1) == Some(':') {
-                let class_chars = self.parse_named_class()?;
-                for char in class_chars {
-                    chars.insert(char);
-                }
+                ranges.extend(self.parse_named_class()?);
                 continue;
             }
 
-            if c == '\\' {
+            let Some(start) = self.parse_class_char(&mut ranges)? else {
+                continue;
+            };
+            // A `-` right before the closing `]` is part of the set
+            if self.current_char() == Some('-') && self.peek(1).is_some_and(|c| c != ']') {
                 self.advance();
-                self.parse_escape_in_class(&mut chars)?;
-            } else if let Some('-') = self.peek(1) {
-                if let Some(end) = self.peek(2) {
-                    if end != ']' {
-                        let start = c;
-                        self.advance();
-                        self.advance();
-                        let end = self.consume_char().unwrap();
-                        self.add_char_range(start, end, &mut chars)?;
-                        continue;
-                    }
+                match self.parse_class_char(&mut ranges)? {
+                    Some(end) => self.add_char_range(start, end, &mut ranges)?,
+                    None => return Err("Invalid character range".to_string()),
                 }
-                chars.insert(c);
-                self.advance();
             } else {
-                chars.insert(c);
-                self.advance();
+                ranges.push((start, start));
             }
         }
 
@@ -440,10 +612,11 @@ impl RegexParser {
         }
         self.advance();
 
+        let class = ClassSet::from_ranges(ranges);
         Ok(if negated {
-            Regex::NegatedCharClass(chars)
+            Regex::NegatedCharClass(class)
         } else {
-            Regex::CharClass(chars)
+            Regex::CharClass(class)
         })
     }
 
@@ -474,46 +647,37 @@ impl RegexParser {
         self.advance();
 
         let class_name_str = class_name.as_str();
-        if let Some(chars) = self.get_named_class(class_name_str) {
+        if let Some(ranges) = self.get_named_class(class_name_str) {
+            let class = ClassSet::from_ranges(ranges);
             Ok(if negated {
-                Regex::NegatedCharClass(chars.clone())
+                Regex::NegatedCharClass(class)
             } else {
-                Regex::CharClass(chars.clone())
+                Regex::CharClass(class)
             })
         } else {
             Err(format!("Unknown POSIX character class '{}'", class_name))
         }
     }
 
-    fn get_named_class(&self, name: &str) -> Option<BTreeSet<char>> {
+    fn get_named_class(&self, name: &str) -> Option<Vec<(char, char)>> {
         match name {
-            "alpha" => Some(('a'..='z').chain('A'..='Z').collect()),
-            "digit" => Some(('0'..='9').collect()),
-            "alnum" => Some(('a'..='z').chain('A'..='Z').chain('0'..='9').collect()),
-            "space" => Some(
-                [' ', '\t', '\n', '\r', '\u{000B}', '\u{000C}']
-                    .iter()
-                    .cloned()
-                    .collect(),
-            ),
-            "punct" => Some("!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".chars().collect()),
-            "graph" => Some((0x21..=0x7E).filter_map(|c| char::from_u32(c)).collect()),
-            "print" => Some((0x20..=0x7E).filter_map(|c| char::from_u32(c)).collect()),
-            "xdigit" => Some(('0'..='9').chain('a'..='f').chain('A'..='F').collect()),
-            "blank" => Some([' ', '\t'].iter().cloned().collect()),
-            "cntrl" => Some(
-                (0x00..=0x1F)
-                    .chain(0x7F..=0x7F)
-                    .filter_map(|c| char::from_u32(c))
-                    .collect(),
-            ),
-            "lower" => Some(('a'..='z').collect()),
-            "upper" => Some(('A'..='Z').collect()),
+            "alpha" => Some(vec![('A', 'Z'), ('a', 'z')]),
+            "digit" => Some(vec![('0', '9')]),
+            "alnum" => Some(vec![('0', '9'), ('A', 'Z'), ('a', 'z')]),
+            "space" => Some(vec![('\t', '\r'), (' ', ' ')]),
+            "punct" => Some(vec![('!', '/'), (':', '@'), ('[', '`'), ('{', '~')]),
+            "graph" => Some(vec![('!', '~')]),
+            "print" => Some(vec![(' ', '~')]),
+            "xdigit" => Some(vec![('0', '9'), ('A', 'F'), ('a', 'f')]),
+            "blank" => Some(vec![('\t', '\t'), (' ', ' ')]),
+            "cntrl" => Some(vec![('\0', '\x1F'), ('\x7F', '\x7F')]),
+            "lower" => Some(vec![('a', 'z')]),
+            "upper" => Some(vec![('A', 'Z')]),
             _ => None,
         }
     }
 
-    fn parse_named_class(&mut self) -> Result<BTreeSet<char>, String> {
+    fn parse_named_class(&mut self) -> Result<Vec<(char, char)>, String> {
         self.advance();
 
         if !self.match_string(":") {
@@ -534,8 +698,8 @@ impl RegexParser {
         }
 
         let class_name_str = class_name.as_str();
-        if let Some(chars) = self.get_named_class(class_name_str) {
-            Ok(chars.clone())
+        if let Some(ranges) = self.get_named_class(class_name_str) {
+            Ok(ranges)
         } else {
             Err(format!("Unknown named character class '{}'", class_name))
         }
@@ -545,75 +709,35 @@ impl RegexParser {
         &self,
         start: char,
         end: char,
-        chars: &mut BTreeSet<char>,
+        ranges: &mut Vec<(char, char)>,
     ) -> Result<(), String> {
         if start > end {
             return Err("Invalid character range".to_string());
         }
-        for c in start..=end {
-            chars.insert(c);
-        }
+        ranges.push((start, end));
         Ok(())
     }
 
-    fn parse_escape_in_class(&mut self, chars: &mut BTreeSet<char>) -> Result<(), String> {
-        match self.current_char() {
-            Some('d') => {
-                ('0'..='9').for_each(|c| {
-                    chars.insert(c);
-                });
-                self.advance();
-            }
-            Some('w') => {
-                ('a'..='z').chain('A'..='Z').chain('0'..='9').for_each(|c| {
-                    chars.insert(c);
-                });
-                chars.insert('_');
-                self.advance();
-            }
-            Some('s') => {
-                [' ', '\t', '\n', '\r', '\u{000B}', '\u{000C}']
-                    .iter()
-                    .for_each(|&c| {
-                        chars.insert(c);
-                    });
-                self.advance();
-            }
-            Some('a') => {
-                chars.insert('\u{0007}');
-                self.advance();
-            }
-            Some('b') => {
-                chars.insert('\u{0008}');
-                self.advance();
-            }
-            Some('f') => {
-                chars.insert('\u{000C}');
-                self.advance();
-            }
-            Some('n') => {
-                chars.insert('\n');
-                self.advance();
-            }
-            Some('r') => {
-                chars.insert('\r');
-                self.advance();
-            }
-            Some('t') => {
-                chars.insert('\t');
-                self.advance();
-            }
-            Some('v') => {
-                chars.insert('\u{000B}');
-                self.advance();
-            }
-            Some(c) => {
-                chars.insert(c);
-                self.advance();
-            }
-            None => return Err("Escape at end of pattern".to_string()),
+    // A char of a bracket expression, escapes included. Escapes for a set
+    // of chars like `\d` are added to `ranges` and give None.
+    fn parse_class_char(&mut self, ranges: &mut Vec<(char, char)>) -> Result<Option<char>, String> {
+        match self.consume_char() {
+            Some('\\') => self.parse_escape_in_class(ranges),
+            Some(c) => Ok(Some(c)),
+            None => Err("Unclosed character class".to_string()),
         }
-        Ok(())
+    }
+
+    fn parse_escape_in_class(&mut self, ranges: &mut Vec<(char, char)>) -> Result<Option<char>, String> {
+        let set = match self.current_char() {
+            Some('d') => DIGIT_RANGES.as_slice(),
+            Some('w') => WORD_RANGES.as_slice(),
+            Some('s') => SPACE_RANGES.as_slice(),
+            _ => return self.parse_escaped_char().map(Some),
+        };
+        self.advance();
+        ranges.extend_from_slice(set);
+        Ok(None)
     }
 
     fn parse_escape(&mut self) -> Result<Regex, String> {
@@ -621,69 +745,63 @@ impl RegexParser {
         match self.current_char() {
             Some('d') => {
                 self.advance();
-                Ok(Regex::CharClass(('0'..='9').collect()))
+                Ok(Regex::CharClass(ClassSet::from_ranges(DIGIT_RANGES.to_vec())))
             }
             Some('D') => {
                 self.advance();
-                let mut set: BTreeSet<char> = (0..=127).filter_map(|c| char::from_u32(c)).collect();
-                set.retain(|c| !c.is_ascii_digit());
-                Ok(Regex::NegatedCharClass(set))
+                Ok(Regex::NegatedCharClass(ClassSet::from_ranges(DIGIT_RANGES.to_vec())))
             }
             Some('w') => {
                 self.advance();
-                let mut set: BTreeSet<char> =
-                    ('a'..='z').chain('A'..='Z').chain('0'..='9').collect();
-                set.insert('_');
-                Ok(Regex::CharClass(set))
+                Ok(Regex::CharClass(ClassSet::from_ranges(WORD_RANGES.to_vec())))
             }
             Some('W') => {
                 self.advance();
-                let mut set: BTreeSet<char> = (0..=127).filter_map(|c| char::from_u32(c)).collect();
-                set.retain(|c| !c.is_alphanumeric() && *c != '_');
-                Ok(Regex::NegatedCharClass(set))
+                Ok(Regex::NegatedCharClass(ClassSet::from_ranges(WORD_RANGES.to_vec())))
             }
             Some('s') => {
                 self.advance();
-                Ok(Regex::CharClass(
-                    [' ', '\t', '\n', '\r', '\u{000B}', '\u{000C}']
-                        .iter()
-                        .cloned()
-                        .collect(),
-                ))
+                Ok(Regex::CharClass(ClassSet::from_ranges(SPACE_RANGES.to_vec())))
             }
             Some('S') => {
                 self.advance();
-                let mut set: BTreeSet<char> = (0..=127).filter_map(|c| char::from_u32(c)).collect();
-                set.retain(|c| ![' ', '\t', '\n', '\r', '\u{000B}', '\u{000C}'].contains(c));
-                Ok(Regex::NegatedCharClass(set))
+                Ok(Regex::NegatedCharClass(ClassSet::from_ranges(SPACE_RANGES.to_vec())))
             }
+            _ => self.parse_escaped_char().map(Regex::Char),
+        }
+    }
+
+    // The char after a backslash: a control char like `\n`, an octal, `\x`
+    // or `\u{...}` code, or the char itself
+    fn parse_escaped_char(&mut self) -> Result<char, String> {
+        match self.current_char() {
             Some('a') => {
                 self.advance();
-                Ok(Regex::Char('\u{0007}'))
+                Ok('\u{0007}')
             }
             Some('b') => {
                 self.advance();
-                Ok(Regex::Char('\u{0008}'))
+                Ok('\u{0008}')
             }
             Some('f') => {
                 self.advance();
-                Ok(Regex::Char('\u{000C}'))
+                Ok('\u{000C}')
             }
             Some('n') => {
                 self.advance();
-                Ok(Regex::Char('\n'))
+                Ok('\n')
             }
             Some('r') => {
                 self.advance();
-                Ok(Regex::Char('\r'))
+                Ok('\r')
             }
             Some('t') => {
                 self.advance();
-                Ok(Regex::Char('\t'))
+                Ok('\t')
             }
             Some('v') => {
                 self.advance();
-                Ok(Regex::Char('\u{000B}'))
+                Ok('\u{000B}')
             }
             Some(c) if c.is_digit(8) => {
                 let mut octal = String::new();
@@ -704,11 +822,7 @@ impl RegexParser {
                 let value = u32::from_str_radix(&octal, 8)
                     .map_err(|_| "Invalid octal escape".to_string())?;
 
-                if let Some(c) = char::from_u32(value) {
-                    Ok(Regex::Char(c))
-                } else {
-                    Err("Invalid character code".to_string())
-                }
+                char::from_u32(value).ok_or_else(|| "Invalid character code".to_string())
             }
             Some('x') => {
                 self.advance();
@@ -716,7 +830,7 @@ impl RegexParser {
 
                 for _ in 0..2 {
                     if let Some(digit) = self.current_char() {
-                        if digit.is_digit(16) {
+                        if digit.is_ascii_hexdigit() {
                             hex.push(digit);
                             self.advance();
                         } else {
@@ -730,15 +844,29 @@ impl RegexParser {
                 let value =
                     u32::from_str_radix(&hex, 16).map_err(|_| "Invalid hex escape".to_string())?;
 
-                if let Some(c) = char::from_u32(value) {
-                    Ok(Regex::Char(c))
-                } else {
-                    Err("Invalid character code".to_string())
+                char::from_u32(value).ok_or_else(|| "Invalid character code".to_string())
+            }
+            Some('u') => {
+                self.advance();
+                if !self.match_string("{") {
+                    return Err("Expected '{' after \\u".to_string());
                 }
+
+                let mut hex = String::new();
+                while let Some(digit) = self.current_char().filter(char::is_ascii_hexdigit) {
+                    hex.push(digit);
+                    self.advance();
+                }
+                if hex.is_empty() || hex.len() > 6 || !self.match_string("}") {
+                    return Err("Expected 1 to 6 hex digits and '}' in \\u{...} escape".to_string());
+                }
+
+                let value = u32::from_str_radix(&hex, 16).map_err(|_| "Invalid hex escape".to_string())?;
+                char::from_u32(value).ok_or_else(|| "Invalid character code".to_string())
             }
             Some(c) => {
                 self.advance();
-                Ok(Regex::Char(c))
+                Ok(c)
             }
             None => Err("Escape at end of pattern".to_string()),
         }
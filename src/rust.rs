@@ -0,0 +1,420 @@
+use crate::{byte_classes, CompressedTables, Error, HeadLength, LexFile, Location, DFA};
+
+// Generates a self-contained Rust module instead of C. Rule actions are
+// Rust blocks run from `Iterator::next` with `yytext: &str` in scope; an
+// action can `continue` to drop the token or `self.begin(COND)` to switch
+// start condition. Tokens carry the index of the rule that matched them.
+// `<<EOF>>` actions run once at the end of the input and can still return a
+// last token. There is no REJECT, rules using it are an error. The DFA reads bytes, so the rules should be
+// compiled in UTF-8 mode as `--language=rust` does; otherwise non-ASCII
+// patterns never match a &str and tokens may split its chars.
+pub struct RustGenerator {
+    file: LexFile,
+    dfa: DFA,
+}
+
+impl RustGenerator {
+    pub fn new(file: LexFile, dfa: DFA) -> Result<Self, Error> {
+        if let Some(rule) = file.rules.iter().find(|rule| rule.action.contains("REJECT")) {
+            let mut location = rule.location.clone().unwrap_or_else(|| Location {
+                file: "<rule>".to_string(),
+                line: 1,
+                column: 1,
+                source: format!("{} {}", rule.pattern, rule.action),
+            });
+            if let Some(offset) = location.source.find("REJECT") {
+                location.column = location.source[..offset].chars().count() + 1;
+            }
+            return Err(Error::Rule {
+                location,
+                message: "REJECT is not supported when generating Rust".to_string(),
+            });
+        }
+
+        Ok(RustGenerator { file, dfa })
+    }
+
+    pub fn code(&self) -> String {
+        let mut code = String::new();
+
+        code.push_str(&self.generate_header());
+        code.push_str(&self.generate_tables());
+        code.push_str(&self.generate_trailing_context());
+        code.push_str(&self.generate_types());
+        code.push_str(&self.generate_lexer());
+
+        // Add user-defined code from the lexer file
+        code.push_str(&self.file.code);
+
+        code
+    }
+
+    // Distinct actions in order of first appearance, with the rules that
+    // run them
+    fn actions(&self) -> Vec<(&String, Vec<usize>)> {
+        let mut actions: Vec<(&String, Vec<usize>)> = Vec::new();
+        for (index, rule) in self.file.rules.iter().enumerate() {
+            match actions.iter_mut().find(|(action, _)| **action == rule.action) {
+                Some((_, rules)) => rules.push(index),
+                None => actions.push((&rule.action, vec![index])),
+            }
+        }
+        actions
+    }
+
+    fn generate_header(&self) -> String {
+        let mut header = String::new();
+
+        header.push_str("// Generated by lex, do not edit\n");
+        header.push('\n');
+
+        for line in &self.file.definitions_code {
+            header.push_str(line);
+            header.push('\n');
+        }
+        header.push('\n');
+
+        header.push_str("// Start conditions\n");
+        for (index, condition) in self.file.start_conditions.iter().enumerate() {
+            header.push_str("#[allow(dead_code)]\n");
+            header.push_str(&format!("pub const {}: usize = {};\n", condition.name, index));
+        }
+        header.push('\n');
+
+        header
+    }
+
+    fn generate_tables(&self) -> String {
+        let tables = CompressedTables::new(&self.dfa);
+        let mut code = String::new();
+
+        // Start states, indexed by 2 * start condition + at beginning of line
+        code.push_str(&rust_array("START_STATES", "usize", &self.dfa.start_states));
+
+        // Equivalence class of each input byte
+        code.push_str(&rust_array("EC", "usize", &byte_classes(&self.dfa)));
+
+        // Comb-compressed transitions on classes, see `CompressedTables`
+        code.push_str(&rust_array("BASE", "i32", &tables.base));
+        code.push_str(&rust_array("DEFAULT", "i32", &tables.default));
+        code.push_str(&rust_array("NEXT", "i32", &tables.next));
+        code.push_str(&rust_array("CHECK", "i32", &tables.check));
+
+        // Rule matched in each state, -1 for states that do not accept
+        let count = self.dfa.states.iter().max().map_or(0, |max| max + 1);
+        let state_rules: Vec<i32> = (0..count)
+            .map(|state| {
+                self.dfa
+                    .rule_index(state)
+                    .filter(|_| self.dfa.final_states.contains(&state))
+                    .map_or(-1, |rule| rule as i32)
+            })
+            .collect();
+        code.push_str(&rust_array("RULES", "i32", &state_rules));
+
+        code.push_str("fn transition(state: usize, byte: u8) -> Option<usize> {\n");
+        code.push_str("    let mut state = state as i32;\n");
+        code.push_str("    while state != -1 {\n");
+        code.push_str("        let index = BASE[state as usize] as usize + EC[byte as usize];\n");
+        code.push_str("        if CHECK[index] == state {\n");
+        code.push_str("            return usize::try_from(NEXT[index]).ok();\n");
+        code.push_str("        }\n");
+        code.push_str("        state = DEFAULT[state as usize];\n");
+        code.push_str("    }\n");
+        code.push_str("    None\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code
+    }
+
+    fn generate_trailing_context(&self) -> String {
+        let mut code = String::new();
+
+        code.push_str(&format!("const HEAD_MARKERS: usize = {};\n", self.dfa.head_marker_count()));
+        code.push('\n');
+
+        // Remember where the head of a variable-length `r/s` rule ended
+        code.push_str("#[allow(unused_variables)]\n");
+        code.push_str("fn mark_head_ends(state: usize, pos: usize, head_pos: &mut [usize; HEAD_MARKERS]) {\n");
+        code.push_str("    match state {\n");
+        for (state, markers) in &self.dfa.head_ends {
+            code.push_str(&format!("        {} => {{\n", state));
+            for marker in markers {
+                code.push_str(&format!("            head_pos[{}] = pos;\n", marker));
+            }
+            code.push_str("        }\n");
+        }
+        code.push_str("        _ => {}\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        // Length of the text to keep when a match ends in `state`
+        code.push_str("#[allow(unused_variables)]\n");
+        code.push_str("fn head_length(state: usize, length: usize, head_pos: &[usize; HEAD_MARKERS]) -> usize {\n");
+        code.push_str("    match state {\n");
+        for (state, head_length) in &self.dfa.head_lengths {
+            let value = match head_length {
+                HeadLength::Fixed(head) => head.to_string(),
+                HeadLength::FromTail(tail) => format!("length - {}", tail),
+                HeadLength::Marker(marker) => format!("head_pos[{}]", marker),
+            };
+            code.push_str(&format!("        {} => {},\n", state, value));
+        }
+        code.push_str("        _ => length,\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code
+    }
+
+    fn generate_types(&self) -> String {
+        let mut code = String::new();
+
+        code.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq)]\n");
+        code.push_str("pub struct Token<'a> {\n");
+        code.push_str("    // Index of the rule in the rules section\n");
+        code.push_str("    pub rule: usize,\n");
+        code.push_str("    pub text: &'a [u8],\n");
+        code.push_str("    pub start: usize,\n");
+        code.push_str("    pub end: usize,\n");
+        code.push_str("    // 1-based position of the first char\n");
+        code.push_str("    pub line: usize,\n");
+        code.push_str("    pub column: usize,\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str("impl<'a> Token<'a> {\n");
+        code.push_str("    // Empty when the token is not valid UTF-8, only possible with from_bytes\n");
+        code.push_str("    pub fn as_str(&self) -> &'a str {\n");
+        code.push_str("        std::str::from_utf8(self.text).unwrap_or_default()\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str("// Input byte that no rule matches\n");
+        code.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq)]\n");
+        code.push_str("pub struct LexError {\n");
+        code.push_str("    pub offset: usize,\n");
+        code.push_str("    pub byte: u8,\n");
+        code.push_str("    pub line: usize,\n");
+        code.push_str("    pub column: usize,\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str("impl std::fmt::Display for LexError {\n");
+        code.push_str("    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {\n");
+        code.push_str("        write!(f, \"{}:{}: unexpected byte 0x{:02X}\", self.line, self.column, self.byte)\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str("impl std::error::Error for LexError {}\n");
+        code.push('\n');
+
+        code
+    }
+
+    fn generate_lexer(&self) -> String {
+        let mut code = String::new();
+
+        code.push_str("pub struct Lexer<'a> {\n");
+        code.push_str("    input: &'a [u8],\n");
+        code.push_str("    pos: usize,\n");
+        code.push_str("    line: usize,\n");
+        code.push_str("    column: usize,\n");
+        code.push_str("    start_condition: usize,\n");
+        code.push_str("    at_bol: bool,\n");
+        if !self.file.eof_rules.is_empty() {
+            code.push_str("    // The <<EOF>> action already ran\n");
+            code.push_str("    at_eof: bool,\n");
+        }
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str("#[allow(dead_code)]\n");
+        code.push_str("impl<'a> Lexer<'a> {\n");
+        code.push_str("    pub fn new(input: &'a str) -> Self {\n");
+        code.push_str("        Self::from_bytes(input.as_bytes())\n");
+        code.push_str("    }\n");
+        code.push('\n');
+        code.push_str("    pub fn from_bytes(input: &'a [u8]) -> Self {\n");
+        code.push_str("        Lexer {\n");
+        code.push_str("            input,\n");
+        code.push_str("            pos: 0,\n");
+        code.push_str("            line: 1,\n");
+        code.push_str("            column: 1,\n");
+        code.push_str("            start_condition: INITIAL,\n");
+        code.push_str("            at_bol: true,\n");
+        if !self.file.eof_rules.is_empty() {
+            code.push_str("            at_eof: false,\n");
+        }
+        code.push_str("        }\n");
+        code.push_str("    }\n");
+        code.push('\n');
+        code.push_str("    pub fn begin(&mut self, condition: usize) {\n");
+        code.push_str("        self.start_condition = condition;\n");
+        code.push_str("    }\n");
+        code.push('\n');
+        code.push_str("    pub fn start_condition(&self) -> usize {\n");
+        code.push_str("        self.start_condition\n");
+        code.push_str("    }\n");
+        code.push('\n');
+
+        // Move past `length` bytes, counting lines and chars
+        code.push_str("    fn advance(&mut self, length: usize) {\n");
+        code.push_str("        for &byte in &self.input[self.pos..self.pos + length] {\n");
+        code.push_str("            if byte == b'\\n' {\n");
+        code.push_str("                self.line += 1;\n");
+        code.push_str("                self.column = 1;\n");
+        code.push_str("            } else if byte & 0xC0 != 0x80 {\n");
+        code.push_str("                self.column += 1;\n");
+        code.push_str("            }\n");
+        code.push_str("        }\n");
+        code.push_str("        self.pos += length;\n");
+        code.push_str("        self.at_bol = self.input[self.pos - 1] == b'\\n';\n");
+        code.push_str("    }\n");
+        code.push('\n');
+
+        // Longest match from the current position: (rule, head length)
+        code.push_str("    fn longest_match(&self) -> Option<(usize, usize)> {\n");
+        code.push_str("        let mut state = START_STATES[2 * self.start_condition + self.at_bol as usize];\n");
+        code.push_str("        let mut head_pos = [0; HEAD_MARKERS];\n");
+        code.push_str("        let mut matched = None;\n");
+        code.push('\n');
+        code.push_str("        mark_head_ends(state, 0, &mut head_pos);\n");
+        code.push_str("        for (i, &byte) in self.input[self.pos..].iter().enumerate() {\n");
+        code.push_str("            state = match transition(state, byte) {\n");
+        code.push_str("                Some(next) => next,\n");
+        code.push_str("                None => break,\n");
+        code.push_str("            };\n");
+        code.push_str("            mark_head_ends(state, i + 1, &mut head_pos);\n");
+        code.push_str("            if let Ok(rule) = usize::try_from(RULES[state]) {\n");
+        code.push_str("                // A match that keeps no text would never make progress\n");
+        code.push_str("                let length = head_length(state, i + 1, &head_pos);\n");
+        code.push_str("                if length > 0 {\n");
+        code.push_str("                    matched = Some((rule, length));\n");
+        code.push_str("                }\n");
+        code.push_str("            }\n");
+        code.push_str("        }\n");
+        code.push('\n');
+        code.push_str("        matched\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code.push_str("impl<'a> Iterator for Lexer<'a> {\n");
+        code.push_str("    type Item = Result<Token<'a>, LexError>;\n");
+        code.push('\n');
+        code.push_str("    #[allow(unreachable_code, clippy::never_loop)]\n");
+        code.push_str("    fn next(&mut self) -> Option<Self::Item> {\n");
+        code.push_str("        while self.pos < self.input.len() {\n");
+        code.push_str("            let (start, line, column) = (self.pos, self.line, self.column);\n");
+        code.push_str("            let Some((rule, length)) = self.longest_match() else {\n");
+        code.push_str("                let byte = self.input[start];\n");
+        code.push_str("                self.advance(1);\n");
+        code.push_str("                return Some(Err(LexError { offset: start, byte, line, column }));\n");
+        code.push_str("            };\n");
+        code.push('\n');
+        code.push_str("            // Trailing context is not consumed and gets scanned again\n");
+        code.push_str("            self.advance(length);\n");
+        code.push('\n');
+        code.push_str("            let token = Token {\n");
+        code.push_str("                rule,\n");
+        code.push_str("                text: &self.input[start..self.pos],\n");
+        code.push_str("                start,\n");
+        code.push_str("                end: self.pos,\n");
+        code.push_str("                line,\n");
+        code.push_str("                column,\n");
+        code.push_str("            };\n");
+        code.push_str("            #[allow(unused_variables)]\n");
+        code.push_str("            let yytext = token.as_str();\n");
+        code.push('\n');
+        code.push_str("            match rule {\n");
+        for (action, rules) in self.actions() {
+            let rules: Vec<String> = rules.iter().map(|rule| rule.to_string()).collect();
+            code.push_str(&format!("                {} => {{\n", rules.join(" | ")));
+            for line in action.lines() {
+                code.push_str("                    ");
+                code.push_str(line);
+                code.push('\n');
+            }
+            code.push_str("                }\n");
+        }
+        code.push_str("                _ => unreachable!(),\n");
+        code.push_str("            }\n");
+        code.push('\n');
+        code.push_str("            return Some(Ok(token));\n");
+        code.push_str("        }\n");
+        code.push('\n');
+        if !self.file.eof_rules.is_empty() {
+            code.push_str(&self.generate_eof_actions());
+        }
+        code.push_str("        None\n");
+        code.push_str("    }\n");
+        code.push_str("}\n");
+        code.push('\n');
+
+        code
+    }
+
+    // Run once the input is used up, before the iterator ends
+    fn generate_eof_actions(&self) -> String {
+        let mut code = String::new();
+
+        code.push_str("        if !self.at_eof {\n");
+        code.push_str("            self.at_eof = true;\n");
+        code.push_str("            #[allow(unused_variables)]\n");
+        code.push_str("            let yytext = \"\";\n");
+        code.push_str("            match self.start_condition {\n");
+        let mut default = None;
+        for rule in &self.file.eof_rules {
+            if rule.start_conditions.is_empty() {
+                default = Some(rule);
+                continue;
+            }
+            let conditions: Vec<String> = rule.start_conditions.iter().map(|c| c.to_string()).collect();
+            code.push_str(&format!("                {} => {{\n", conditions.join(" | ")));
+            code.push_str(&indent_lines(&rule.action, "                    "));
+            code.push_str("                }\n");
+        }
+        code.push_str("                _ => {\n");
+        if let Some(rule) = default {
+            code.push_str(&indent_lines(&rule.action, "                    "));
+        }
+        code.push_str("                }\n");
+        code.push_str("            }\n");
+        code.push_str("        }\n");
+        code.push('\n');
+
+        code
+    }
+}
+
+fn indent_lines(text: &str, indent: &str) -> String {
+    let mut code = String::new();
+    for line in text.lines() {
+        code.push_str(indent);
+        code.push_str(line);
+        code.push('\n');
+    }
+    code
+}
+
+fn rust_array<T: ToString>(name: &str, ty: &str, values: &[T]) -> String {
+    let mut array = format!("const {}: [{}; {}] = [", name, ty, values.len());
+
+    for (i, value) in values.iter().enumerate() {
+        if i % 16 == 0 {
+            array.push_str("\n   ");
+        }
+        array.push(' ');
+        array.push_str(&value.to_string());
+        array.push(',');
+    }
+
+    array.push_str("\n];\n\n");
+    array
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use crate::{StateID, DFA};
+
+// Transitions of one state: equivalence class -> next state
+type Row = BTreeMap<usize, i32>;
+
+fn rows(dfa: &DFA) -> Vec<Row> {
+    let count = dfa.states.iter().max().map_or(0, |max| max + 1);
+    let mut rows = vec![Row::new(); count];
+
+    for (&(state, class), &next) in &dfa.transitions {
+        rows[state].insert(class, next as i32);
+    }
+
+    rows
+}
+
+// Equivalence class of every input byte, the scanner reads bytes
+pub fn byte_classes(dfa: &DFA) -> Vec<i32> {
+    (0..=255u8).map(|byte| dfa.class_of(byte as char) as i32).collect()
+}
+
+// Uncompressed `states x classes` table, -1 marks the error state
+pub fn full_table(dfa: &DFA) -> Vec<Vec<i32>> {
+    rows(dfa)
+        .into_iter()
+        .map(|row| {
+            let mut full = vec![-1; dfa.class_count()];
+            for (class, next) in row {
+                full[class] = next;
+            }
+            full
+        })
+        .collect()
+}
+
+// Comb-compressed tables in the style of flex. The transition of `state` on
+// class `c` is `next[base[state] + c]` when `check[base[state] + c] == state`,
+// otherwise it is looked up again from `default[state]`. A default of -1
+// means there is no transition.
+pub struct CompressedTables {
+    pub base: Vec<i32>,
+    pub default: Vec<i32>,
+    pub next: Vec<i32>,
+    pub check: Vec<i32>,
+}
+
+impl CompressedTables {
+    pub fn new(dfa: &DFA) -> Self {
+        let rows = rows(dfa);
+        let mut tables = CompressedTables {
+            base: vec![0; rows.len()],
+            default: vec![-1; rows.len()],
+            next: Vec::new(),
+            check: Vec::new(),
+        };
+
+        for (state, row) in rows.iter().enumerate() {
+            let (default, entries) = Self::best_default(&rows[..state], row);
+            tables.default[state] = default.map_or(-1, |default| default as i32);
+            tables.place(state, &entries);
+        }
+
+        // Pad so any `base + c` lookup stays in bounds
+        let size = tables.base.iter().max().map_or(0, |&max| max as usize) + dfa.class_count();
+        tables.next.resize(size, -1);
+        tables.check.resize(size, -1);
+
+        tables
+    }
+
+    // Pick the earlier state whose row differs least from `row`, and return
+    // the entries that still have to be stored (-1 where `row` has no
+    // transition but the default does)
+    fn best_default(previous: &[Row], row: &Row) -> (Option<StateID>, Row) {
+        let mut best = (None, row.clone());
+
+        for (candidate, other) in previous.iter().enumerate() {
+            let mut entries = Row::new();
+            for (&c, &next) in row {
+                if other.get(&c) != Some(&next) {
+                    entries.insert(c, next);
+                }
+            }
+            for &c in other.keys() {
+                if !row.contains_key(&c) {
+                    entries.insert(c, -1);
+                }
+            }
+
+            if entries.len() < best.1.len() {
+                best = (Some(candidate), entries);
+            }
+        }
+
+        best
+    }
+
+    // First-fit placement of a state's entries into the shared arrays
+    fn place(&mut self, state: StateID, entries: &Row) {
+        let base = (0..)
+            .find(|&base| {
+                entries
+                    .keys()
+                    .all(|&c| self.check.get(base + c).is_none_or(|&owner| owner == -1))
+            })
+            .unwrap();
+
+        if let Some(&last) = entries.keys().last() {
+            if self.next.len() <= base + last {
+                self.next.resize(base + last + 1, -1);
+                self.check.resize(base + last + 1, -1);
+            }
+        }
+
+        for (&c, &next) in entries {
+            self.next[base + c] = next;
+            self.check[base + c] = state as i32;
+        }
+
+        self.base[state] = base as i32;
+    }
+
+    pub fn transition(&self, state: StateID, class: usize) -> Option<StateID> {
+        let mut state = state as i32;
+        while state != -1 {
+            let index = self.base[state as usize] as usize + class;
+            if self.check[index] == state {
+                return (self.next[index] != -1).then_some(self.next[index] as StateID);
+            }
+            state = self.default[state as usize];
+        }
+        None
+    }
+}
@@ -1,11 +1,23 @@
-use std::collections::BTreeSet;
 use std::fmt::{Display, Formatter, Result};
 
+use crate::ClassSet;
+
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum TransitionSymbol {
     Epsilon,
     Char(char),
-    CharClass(BTreeSet<char>),
+    CharClass(ClassSet),
+}
+
+impl TransitionSymbol {
+    // The chars the transition is taken on, empty for epsilon
+    pub fn chars(&self) -> ClassSet {
+        match self {
+            TransitionSymbol::Epsilon => ClassSet::new(),
+            TransitionSymbol::Char(c) => ClassSet::from(*c),
+            TransitionSymbol::CharClass(class) => class.clone(),
+        }
+    }
 }
 
 impl Display for TransitionSymbol {
@@ -13,13 +25,7 @@ impl Display for TransitionSymbol {
         match self {
             TransitionSymbol::Epsilon => write!(f, "ε"),
             TransitionSymbol::Char(c) => write!(f, "{}", c),
-            TransitionSymbol::CharClass(set) => {
-                write!(f, "[")?;
-                for c in set {
-                    write!(f, "{}", c)?;
-                }
-                write!(f, "]")
-            }
+            TransitionSymbol::CharClass(class) => write!(f, "[{}]", class),
         }
     }
 }
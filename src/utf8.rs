@@ -0,0 +1,69 @@
+// Byte ranges matching the UTF-8 encoding of a range of code points, one
+// range per byte of the encoding
+pub type Utf8Sequence = Vec<(u8, u8)>;
+
+const SURROGATES: (u32, u32) = (0xD800, 0xDFFF);
+
+// Split the code points `start..=end` into sequences of byte ranges, so that
+// a code point is in the range exactly when its encoding matches one of the
+// sequences. This is the construction of RE2 and regex-automata: split at
+// the lengths of the encoding, then until every byte but the first covers
+// whole continuation ranges.
+pub fn utf8_sequences(start: char, end: char) -> Vec<Utf8Sequence> {
+    let mut sequences = Vec::new();
+    split_range(start as u32, end as u32, &mut sequences);
+    sequences
+}
+
+fn split_range(start: u32, end: u32, sequences: &mut Vec<Utf8Sequence>) {
+    if start > end {
+        return;
+    }
+
+    // Surrogates have no encoding
+    if start < SURROGATES.0 && end > SURROGATES.1 {
+        split_range(start, SURROGATES.0 - 1, sequences);
+        split_range(SURROGATES.1 + 1, end, sequences);
+        return;
+    }
+
+    // Every part must have the same encoded length
+    for max in [0x7F, 0x7FF, 0xFFFF] {
+        if start <= max && max < end {
+            split_range(start, max, sequences);
+            split_range(max + 1, end, sequences);
+            return;
+        }
+    }
+
+    if end <= 0x7F {
+        sequences.push(vec![(start as u8, end as u8)]);
+        return;
+    }
+
+    // The last `i` bytes must either all be fixed or all cover 0x80..=0xBF
+    for i in 1..4 {
+        let mask = (1 << (6 * i)) - 1;
+        if start & !mask != end & !mask {
+            if start & mask != 0 {
+                split_range(start, start | mask, sequences);
+                split_range((start | mask) + 1, end, sequences);
+                return;
+            }
+            if end & mask != mask {
+                split_range(start, (end & !mask) - 1, sequences);
+                split_range(end & !mask, end, sequences);
+                return;
+            }
+        }
+    }
+
+    let (mut first, mut last) = ([0; 4], [0; 4]);
+    let first = encode(start, &mut first);
+    let last = encode(end, &mut last);
+    sequences.push(first.iter().copied().zip(last.iter().copied()).collect());
+}
+
+fn encode(code_point: u32, buffer: &mut [u8; 4]) -> &[u8] {
+    char::from_u32(code_point).unwrap().encode_utf8(buffer).as_bytes()
+}
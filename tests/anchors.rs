@@ -0,0 +1,24 @@
+mod common;
+
+use common::tokens;
+use lex::Rule;
+
+#[test]
+fn start_anchor_only_matches_at_beginning_of_line() {
+    let patterns = ["^a", "a", "\\n", "[ ]"];
+    assert_eq!(tokens(&patterns, "a a\na"), r#""a":0 " ":3 "a":1 "\n":2 "a":0"#);
+}
+
+#[test]
+fn end_anchor_needs_a_newline() {
+    let patterns = ["a$", "a", "\\n"];
+    assert_eq!(tokens(&patterns, "a\naa\n"), r#""a":0 "\n":2 "a":1 "a":0 "\n":2"#);
+    // No newline at the end of input, so `a$` does not match
+    assert_eq!(tokens(&patterns, "a"), r#""a":1"#);
+}
+
+#[test]
+fn anchors_are_rejected_mid_pattern() {
+    assert!(Rule::new("a^b".to_string(), String::new()).is_err());
+    assert!(Rule::new("a$b".to_string(), String::new()).is_err());
+}
@@ -0,0 +1,41 @@
+use lex::{Args, ArgsParser, Backend, Language};
+
+fn parse(args: &[&str]) -> Result<Args, String> {
+    ArgsParser::from_args(args.iter().map(|arg| arg.to_string()).collect()).parse()
+}
+
+#[test]
+fn short_and_long_options_take_values() {
+    let args = parse(&["-tdPfoo", "--outfile", "out.c", "--header-file=out.h", "-Cf", "spec.l"]).unwrap();
+
+    assert!(args.stdout);
+    assert!(args.debug);
+    assert_eq!(args.prefix.as_deref(), Some("foo"));
+    assert_eq!(args.outfile.as_deref(), Some("out.c"));
+    assert_eq!(args.header_file.as_deref(), Some("out.h"));
+    assert_eq!(args.backend, Backend::Full);
+    assert_eq!(args.input.as_deref(), Some("spec.l"));
+}
+
+#[test]
+fn dash_reads_stdin() {
+    assert_eq!(parse(&["-"]).unwrap().input, None);
+    assert_eq!(parse(&["--", "-i"]).unwrap().input.as_deref(), Some("-i"));
+    assert_eq!(parse(&["--language=rust"]).unwrap().language, Language::Rust);
+}
+
+#[test]
+fn rust_output_matches_utf8() {
+    assert!(!parse(&["spec.l"]).unwrap().utf8);
+    assert!(parse(&["--language=rust", "spec.l"]).unwrap().utf8);
+}
+
+#[test]
+fn bad_arguments_are_reported() {
+    assert_eq!(parse(&["--bogus"]).unwrap_err(), "Unknown option '--bogus'");
+    assert_eq!(parse(&["-q"]).unwrap_err(), "Unknown option '-q'");
+    assert_eq!(parse(&["-o"]).unwrap_err(), "Option '-o' requires an argument");
+    assert_eq!(parse(&["--help=yes"]).unwrap_err(), "Option '--help' doesn't take an argument");
+    assert!(parse(&["a.l", "b.l"]).is_err());
+    assert!(parse(&["--language=rust", "-d"]).is_err());
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::run_scanner;
+use lex::{Backend, CodeGenerator, LexFile};
+
+const SPEC: &str = r#"%%
+\"[^"]*\" { printf("string %d\n", yyleng); }
+[a-z]+ { printf("word %s\n", yytext); }
+[ \n] {}
+"#;
+
+#[test]
+fn tokens_crossing_the_buffer_end_are_kept_whole() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+    let code = CodeGenerator::new(file, dfa, Backend::Switch).code();
+    assert!(code.contains("#ifndef YY_BUFFER_SIZE\n"));
+
+    // With an 8 byte buffer most tokens cross its end and the long ones
+    // only fit after it grows
+    let code = format!("#define YY_BUFFER_SIZE 8\n{}", code);
+    let string = format!("\"{}\"", "x".repeat(40));
+    let input = format!("ab cdefg {}\nhijklmnopqrstuvwxyz end\n", string);
+    let expected = "word ab\nword cdefg\nstring 42\nword hijklmnopqrstuvwxyz\nword end\n";
+    assert_eq!(run_scanner(&code, &input), expected);
+}
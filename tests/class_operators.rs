@@ -0,0 +1,41 @@
+mod common;
+
+use common::accepts;
+use lex::Regex;
+
+#[test]
+fn difference_and_union_of_classes() {
+    let spec = "CONSONANT [a-z]{-}[aeiou]\n%%\n{CONSONANT}+ {}\n";
+    assert!(accepts(spec, 'b'));
+    assert!(!accepts(spec, 'e'));
+
+    let spec = "%%\n[[:alpha:]]{+}[_] {}\n";
+    assert!(accepts(spec, 'Q'));
+    assert!(accepts(spec, '_'));
+    assert!(!accepts(spec, '1'));
+}
+
+#[test]
+fn operators_chain_and_respect_negation() {
+    let spec = "%%\n[a-z]{-}[aeiou]{+}[E] {}\n";
+    assert!(accepts(spec, 'E'));
+    assert!(!accepts(spec, 'e'));
+
+    let spec = "%%\n[^a]{-}[b] {}\n";
+    assert!(accepts(spec, 'c'));
+    assert!(accepts(spec, '\u{FF}'));
+    assert!(!accepts(spec, 'a'));
+    assert!(!accepts(spec, 'b'));
+
+    let spec = "%%\n[a-c]{-}[^b] {}\n";
+    assert!(accepts(spec, 'b'));
+    assert!(!accepts(spec, 'a'));
+}
+
+#[test]
+fn operands_must_be_classes() {
+    for pattern in ["a{-}[a]", "[a-z]{+}b", "(x){-}[x]", "[a-z]{-}"] {
+        assert!(Regex::new(pattern).is_err(), "{}", pattern);
+    }
+    assert!(Regex::new("[a-z]{2}").is_ok());
+}
@@ -0,0 +1,65 @@
+use lex::{ClassSet, LexFile, Lexer, Regex};
+
+#[test]
+fn ranges_are_merged() {
+    let set = ClassSet::from_ranges(vec![('d', 'f'), ('a', 'c'), ('x', 'x'), ('e', 'h')]);
+
+    assert_eq!(set.ranges(), [('a', 'h'), ('x', 'x')]);
+    assert_eq!(set.len(), 9);
+    assert!(set.contains('x') && !set.contains('i'));
+    assert_eq!(set, "abcdefghx".chars().collect());
+}
+
+#[test]
+fn set_operations() {
+    let letters = ClassSet::from_ranges(vec![('A', 'Z'), ('a', 'z')]);
+    let hex = ClassSet::from_ranges(vec![('0', '9'), ('A', 'F'), ('a', 'f')]);
+
+    assert_eq!(letters.union(&hex).ranges(), [('0', '9'), ('A', 'Z'), ('a', 'z')]);
+    assert_eq!(letters.intersection(&hex).ranges(), [('A', 'F'), ('a', 'f')]);
+    assert_eq!(letters.difference(&hex).ranges(), [('G', 'Z'), ('g', 'z')]);
+    assert_eq!(hex.negate().negate(), hex);
+    assert_eq!(ClassSet::range('\0', '\u{D7FF}').negate().ranges(), [('\u{E000}', char::MAX)]);
+    assert_eq!(ClassSet::full().len(), 0x110000 - 0x800);
+}
+
+#[test]
+fn huge_classes_stay_small() {
+    let spec = format!("%option utf8\n%%\n[{}-{}]+ {{}}\n", '\u{1}', '\u{10FFFF}');
+    let file = LexFile::parse("spec.l", &spec).unwrap();
+    let lexer = Lexer::new(&file).unwrap();
+
+    let tokens: Vec<&str> = lexer.tokens("aé日\u{10FFFF}").map(|token| token.unwrap().text).collect();
+    assert_eq!(tokens, vec!["aé日\u{10FFFF}"]);
+}
+
+#[test]
+fn escapes_in_classes() {
+    let Regex::CharClass(class) = Regex::new(r"[\d\s_]").unwrap() else {
+        panic!("not a class");
+    };
+    assert_eq!(class.ranges(), [('\t', '\r'), (' ', ' '), ('0', '9'), ('_', '_')]);
+
+    let Regex::CharClass(class) = Regex::new(r"[\w\x20-\x22\n-\r]").unwrap() else {
+        panic!("not a class");
+    };
+    assert_eq!(class.ranges(), [('\n', '\r'), (' ', '"'), ('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')]);
+}
+
+#[test]
+fn unicode_escapes() {
+    let Regex::CharClass(class) = Regex::new(r"[\u{0}-\u{10FFFF}]").unwrap() else {
+        panic!("not a class");
+    };
+    assert_eq!(class, ClassSet::full());
+
+    assert!(Regex::new(r"\u{D800}").is_err());
+    assert!(Regex::new(r"\u{1234567}").is_err());
+    assert!(Regex::new(r"[\u{20}-\u{10}]").is_err());
+
+    // Not mistaken for a `{NAME}` reference
+    let file = LexFile::parse("spec.l", "%option utf8\n%%\n\\u{E9}+ {}\n").unwrap();
+    let lexer = Lexer::new(&file).unwrap();
+    let tokens: Vec<&str> = lexer.tokens("éé").map(|token| token.unwrap().text).collect();
+    assert_eq!(tokens, vec!["éé"]);
+}
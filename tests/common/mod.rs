@@ -0,0 +1,91 @@
+// Helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lex::{LexFile, StateID, DFA};
+
+// Temporary file name that is unique across tests running in parallel
+fn temp_path(extension: &str) -> PathBuf {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let id = FILES.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("lex-{}-{}.{}", std::process::id(), id, extension))
+}
+
+// State the DFA reaches from `start` after reading `input`, None once it
+// gets stuck
+pub fn walk(dfa: &DFA, start: StateID, input: impl IntoIterator<Item = char>) -> Option<StateID> {
+    input.into_iter().try_fold(start, |state, c| dfa.next_state(state, c))
+}
+
+// Whether the spec matches `c` as a whole token
+pub fn accepts(spec: &str, c: char) -> bool {
+    let dfa = LexFile::parse("spec.l", spec).unwrap().dfa().unwrap();
+    walk(&dfa, dfa.start_state, [c]).is_some_and(|state| dfa.final_states.contains(&state))
+}
+
+// `"text":rule` for each token the rules built from `patterns` scan in
+// `input`, the text is what yytext holds
+pub fn tokens(patterns: &[&str], input: &str) -> String {
+    let rules: Vec<String> =
+        patterns.iter().enumerate().map(|(index, pattern)| format!("{} {}\n", pattern, index)).collect();
+    let dfa = LexFile::parse("spec.l", &format!("%%\n{}", rules.concat())).unwrap().dfa().unwrap();
+    let tokens: Vec<String> =
+        dfa.simulate(input).iter().map(|(text, rule)| format!("{:?}:{}", text, rule)).collect();
+    tokens.join(" ")
+}
+
+// Compile a generated C scanner with libl and run it on `input`, returning
+// what it prints
+pub fn run_scanner(code: &str, input: &str) -> String {
+    let source = temp_path("c");
+    let binary = temp_path("out");
+    std::fs::write(&source, code).unwrap();
+
+    let libl = concat!(env!("CARGO_MANIFEST_DIR"), "/libl");
+    let status = Command::new("cc")
+        .args(["-Wall", "-Wextra", "-Werror", "-I", libl])
+        .arg(&source)
+        .arg(format!("{}/libl.c", libl))
+        .arg("-o")
+        .arg(&binary)
+        .status()
+        .unwrap();
+    std::fs::remove_file(&source).unwrap();
+    assert!(status.success(), "the generated scanner does not compile");
+
+    let mut scanner = Command::new(&binary).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    scanner.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = scanner.wait_with_output().unwrap();
+    std::fs::remove_file(&binary).unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Compile a generated Rust lexer that has its own `main` and run it on
+// `input`, returning what it prints
+pub fn run_rust_scanner(code: &str, input: &str) -> String {
+    let source = temp_path("rs");
+    let binary = temp_path("out");
+    std::fs::write(&source, code).unwrap();
+
+    let status = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+        .args(["--edition", "2021", "-D", "warnings"])
+        .arg(&source)
+        .arg("-o")
+        .arg(&binary)
+        .status()
+        .unwrap();
+    std::fs::remove_file(&source).unwrap();
+    assert!(status.success(), "the generated lexer does not compile");
+
+    let mut scanner = Command::new(&binary).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    scanner.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = scanner.wait_with_output().unwrap();
+    std::fs::remove_file(&binary).unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
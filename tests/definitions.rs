@@ -0,0 +1,38 @@
+use lex::{Error, LexFile};
+
+fn warnings(spec: &str) -> Vec<String> {
+    let file = LexFile::parse("spec.l", spec).unwrap();
+    file.warnings.iter().map(|warning| warning.message.clone()).collect()
+}
+
+#[test]
+fn undefined_macros_are_errors() {
+    let error = LexFile::parse("spec.l", "%%\n{DIGIT}+ {}\n").err().unwrap();
+    assert!(matches!(error, Error::UnknownMacro { .. }));
+    assert_eq!(error.message(), "Undefined macro '{DIGIT}'");
+    assert_eq!((error.location().unwrap().line, error.location().unwrap().column), (2, 1));
+
+    let error = LexFile::parse("spec.l", "NUMBER {DIGIT}+\n%%\n{NUMBER} {}\n").err().unwrap();
+    assert_eq!(error.message(), "Undefined macro '{DIGIT}'");
+    assert_eq!(error.location().unwrap().line, 1);
+}
+
+#[test]
+fn recursive_macros_are_errors() {
+    let error = LexFile::parse("spec.l", "A x{B}\nB y{C}\nC {A}\n%%\n{A} {}\n").err().unwrap();
+
+    assert!(matches!(error, Error::RecursiveMacro { .. }));
+    assert_eq!(error.message(), "Recursive macro definition: A -> B -> C -> A");
+}
+
+#[test]
+fn definitions_used_only_by_unused_definitions_are_unused() {
+    let spec = "DIGIT [0-9]\nNUMBER {DIGIT}+\nLETTER [a-z]\n%%\n{LETTER} {}\n";
+    assert_eq!(
+        warnings(spec),
+        vec!["Definition 'DIGIT' is never used", "Definition 'NUMBER' is never used"]
+    );
+
+    let spec = "DIGIT [0-9]\nNUMBER {DIGIT}+\n%%\n{NUMBER} {}\n";
+    assert!(warnings(spec).is_empty());
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::walk;
+use lex::{LexFile, Regex};
+
+#[test]
+fn dfa_edges_use_ranges_and_accepting_states_name_their_rule() {
+    let dfa = LexFile::parse("spec.l", "%%\n[a-z]+ {}\n[0-9] {}\n").unwrap().dfa().unwrap().minimize();
+    let dot = dfa.to_dot();
+
+    let word = walk(&dfa, dfa.start_state, "a".chars()).unwrap();
+    let digit = walk(&dfa, dfa.start_state, "0".chars()).unwrap();
+    assert!(dot.contains(&format!("    {} -> {} [label=\"[a-z]\"];\n", dfa.start_state, word)));
+    assert!(dot.contains(&format!("    {} -> {} [label=\"[a-z]\"];\n", word, word)));
+    assert!(dot.contains(&format!("    {} -> {} [label=\"[0-9]\"];\n", dfa.start_state, digit)));
+    assert!(dot.contains(&format!("    {} [shape=doublecircle, label=\"{}\\nrule 0\"];\n", word, word)));
+    assert!(dot.contains(&format!("    {} [shape=doublecircle, label=\"{}\\nrule 1\"];\n", digit, digit)));
+}
+
+#[test]
+fn nfa_edges_use_ranges() {
+    let (nfa, _) = LexFile::parse("spec.l", "%%\n[a-z]+ {}\n").unwrap().nfa();
+    let dot = nfa.to_dot();
+
+    assert!(dot.contains(" [label=\"[a-z]\"];\n"));
+    assert!(dot.contains("\\nrule 0\"];\n"));
+    assert!(!dot.contains("[label=\"a\"]"));
+}
+
+#[test]
+fn regex_tree_has_a_node_per_ast_node() {
+    let dot = Regex::new("[a-z]+|x").unwrap().to_dot();
+
+    let lines: Vec<&str> = dot.lines().skip(2).map(str::trim).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "n0 [label=\"|\"];",
+            "n1 [label=\"+\"];",
+            "n2 [label=\"Class [a-z]\"];",
+            "n1 -> n2;",
+            "n0 -> n1;",
+            "n3 [label=\"Char x\"];",
+            "n0 -> n3;",
+            "}",
+        ]
+    );
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use common::accepts;
+use lex::LexFile;
+
+#[test]
+fn negation_covers_all_bytes_by_default() {
+    for spec in ["%%\n[^\"] {}\n", "%%\n. {}\n", "%%\n\\D {}\n", "%%\n\\W {}\n", "%%\n\\S {}\n"] {
+        assert!(accepts(spec, '\u{E9}'), "{}", spec);
+        assert!(accepts(spec, '\u{FF}'), "{}", spec);
+    }
+    assert!(!accepts("%%\n\\D {}\n", '5'));
+    assert!(!accepts("%%\n\\W {}\n", '_'));
+}
+
+#[test]
+fn seven_bit_option_limits_negation_to_ascii() {
+    let spec = "%option 7bit\n%%\n[^\"] {}\n";
+    assert!(!LexFile::parse("spec.l", spec).unwrap().options.eight_bit);
+    assert!(accepts(spec, '\x7F'));
+    assert!(!accepts(spec, '\u{80}'));
+}
+
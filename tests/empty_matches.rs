@@ -0,0 +1,33 @@
+mod common;
+
+use common::run_scanner;
+use lex::{Backend, CodeGenerator, LexFile};
+
+#[test]
+fn rules_matching_the_empty_string_are_reported() {
+    let file = LexFile::parse("spec.l", "%%\nx {}\n[a-z]* {}\nab*/c {}\n").unwrap();
+
+    let warnings: Vec<_> = file
+        .warnings
+        .iter()
+        .map(|warning| (warning.location.line, warning.location.column, warning.message.as_str()))
+        .collect();
+    assert_eq!(warnings, vec![(3, 1, "Rule can match the empty string, empty matches are skipped")]);
+}
+
+#[test]
+fn generated_scanner_skips_empty_matches() {
+    let spec = r#"%%
+a*/b { printf("head %s\n", yytext); }
+[c-z]* { printf("word %s\n", yytext); }
+\n {}
+"#;
+    let file = LexFile::parse("spec.l", spec).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+    let code = CodeGenerator::new(file, dfa, Backend::Switch).code();
+
+    // The empty head of `a*/b` before "b" and the empty match of `[c-z]*`
+    // before "1" would be found again at the same position forever, so
+    // those chars are reported as unmatched instead
+    assert_eq!(run_scanner(&code, "aab\nxy\n1\nb\n"), "head aa\nword xy\n");
+}
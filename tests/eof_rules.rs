@@ -0,0 +1,30 @@
+use lex::{Error, LexFile};
+
+const SPEC: &str = "%x COMMENT\n%%\n\"/*\" { BEGIN COMMENT; }\n<COMMENT>\"*/\" { BEGIN INITIAL; }\n<COMMENT><<EOF>> { yyterminate(); }\n<<EOF>> { return 0; }\n";
+
+#[test]
+fn eof_rules_are_kept_out_of_the_automaton() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+
+    assert_eq!(file.rules.len(), 2);
+    assert_eq!(file.eof_rules.len(), 2);
+    assert_eq!(file.eof_rules[0].start_conditions, vec![1]);
+    assert_eq!(file.eof_rules[0].action, "{ yyterminate(); }");
+    assert!(file.eof_rules[1].start_conditions.is_empty());
+}
+
+#[test]
+fn unprefixed_eof_rule_is_the_fallback() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+
+    assert_eq!(file.eof_rule(1).unwrap().action, "{ yyterminate(); }");
+    assert_eq!(file.eof_rule(0).unwrap().action, "{ return 0; }");
+}
+
+#[test]
+fn duplicate_eof_rules_are_errors() {
+    let error = LexFile::parse("spec.l", "%x A\n%%\n<A><<EOF>> {}\n<A><<EOF>> {}\n").err().unwrap();
+
+    assert!(matches!(error, Error::Rule { .. }));
+    assert_eq!(error.message(), "Multiple <<EOF>> rules for start condition 'A'");
+}
@@ -0,0 +1,41 @@
+use lex::{byte_classes, ClassSet, LexFile};
+
+const C_SPEC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/c.l");
+
+#[test]
+fn chars_with_the_same_transitions_share_a_class() {
+    let dfa = LexFile::parse("spec.l", "%%\n[a-z]+ {}\n[0-9]+ {}\n").unwrap().dfa().unwrap();
+
+    assert_eq!(dfa.class_count(), 3);
+    assert_eq!(dfa.class_set(dfa.class_of('a')), ClassSet::range('a', 'z'));
+    assert_eq!(dfa.class_set(dfa.class_of('0')), ClassSet::range('0', '9'));
+    // Chars no rule mentions are in class 0, which has no transitions
+    assert_eq!(dfa.class_of('A'), 0);
+}
+
+#[test]
+fn ranges_that_tell_chars_apart_split_classes() {
+    let dfa = LexFile::parse("spec.l", "%%\n[a-z]+ {}\n[m-p]x {}\n").unwrap().dfa().unwrap();
+
+    let class = |c| dfa.class_of(c);
+    assert_eq!(class('a'), class('l'));
+    assert_eq!(class('a'), class('q'));
+    assert_eq!(class('m'), class('p'));
+    assert_ne!(class('a'), class('m'));
+    assert_ne!(class('a'), class('x'));
+    assert_ne!(class('m'), class('x'));
+}
+
+#[test]
+fn byte_classes_agree_with_the_dfa() {
+    let dfa = LexFile::new(C_SPEC).unwrap().dfa().unwrap().minimize();
+    let classes = byte_classes(&dfa);
+
+    for &state in &dfa.states {
+        for byte in 0..=255u8 {
+            let class = classes[byte as usize] as usize;
+            let next = dfa.transitions.get(&(state, class)).copied();
+            assert_eq!(next, dfa.next_state(state, byte as char), "state {} on {}", state, byte);
+        }
+    }
+}
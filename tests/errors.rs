@@ -0,0 +1,59 @@
+use lex::{Error, LexFile};
+
+fn error(spec: &str) -> Error {
+    LexFile::parse("spec.l", spec).err().unwrap()
+}
+
+#[test]
+fn bad_rule_pattern_points_into_the_rule() {
+    let error = error("ID [a-z]\n%%\n  {ID}x[9-0] {}\n");
+
+    assert!(matches!(error, Error::Regex { .. }));
+    assert_eq!(
+        error.render(),
+        concat!(
+            "error: Invalid character range\n",
+            " --> spec.l:3:12\n",
+            "  |\n",
+            "3 |   {ID}x[9-0] {}\n",
+            "  |            ^\n",
+            "  = note: in pattern '[a-z]x[9-0]' after macro expansion\n",
+        )
+    );
+}
+
+#[test]
+fn bad_definition_points_into_the_definition() {
+    let error = error("ID [a-z]\nDIGIT  [9-0]\n%%\n{ID}x{DIGIT}+ {}\n");
+
+    assert_eq!(
+        error.render(),
+        concat!(
+            "error: Invalid character range\n",
+            " --> spec.l:2:12\n",
+            "  |\n",
+            "2 | DIGIT  [9-0]\n",
+            "  |            ^\n",
+            "  = note: in pattern '[a-z]x[9-0]+' after macro expansion\n",
+        )
+    );
+}
+
+#[test]
+fn definition_section_errors_point_at_the_word() {
+    let error = error("%x  A\tB A\n%%\na {}\n");
+    assert_eq!(error.message(), "Start condition 'A' declared twice");
+    assert_eq!(error.location().unwrap().column, 9);
+
+    let file = LexFile::parse("spec.l", "%option  yylineno\t bogus\n%%\na {}\n").unwrap();
+    assert_eq!(
+        file.warnings[0].render(),
+        concat!(
+            "warning: Unknown option 'bogus' is ignored\n",
+            " --> spec.l:1:20\n",
+            "  |\n",
+            "1 | %option  yylineno\t bogus\n",
+            "  |                  \t ^\n",
+        )
+    );
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::walk;
+use lex::{LexFile, Regex};
+
+fn matches(spec: &str, input: &str) -> bool {
+    let file = LexFile::parse("spec.l", spec).unwrap();
+    let dfa = file.dfa().unwrap();
+    // In UTF-8 mode the DFA runs over the bytes of the input
+    let chars: Vec<char> = if file.options.utf8 {
+        input.bytes().map(char::from).collect()
+    } else {
+        input.chars().collect()
+    };
+    walk(&dfa, dfa.start_state, chars).is_some_and(|state| dfa.final_states.contains(&state))
+}
+
+#[test]
+fn scoped_case_insensitivity() {
+    let spec = "%%\n(?i:begin)_end {}\n";
+    assert!(matches(spec, "BeGiN_end"));
+    assert!(!matches(spec, "begin_END"));
+
+    let spec = "%option case-insensitive\n%%\nx(?-i:y) {}\n";
+    assert!(matches(spec, "Xy"));
+    assert!(!matches(spec, "XY"));
+}
+
+#[test]
+fn folding_happens_before_negation() {
+    let spec = "%option case-insensitive\n%%\n[^a] {}\n";
+    assert!(!matches(spec, "A"));
+    assert!(matches(spec, "b"));
+
+    let spec = "%option utf8\n%%\n(?i:[^k])é {}\n";
+    assert!(!matches(spec, "Ké"));
+    assert!(matches(spec, "λé"));
+}
+
+#[test]
+fn dot_matches_newline_only_with_s_flag() {
+    assert!(!matches("%%\n. {}\n", "\n"));
+    assert!(matches("%%\n(?s:.) {}\n", "\n"));
+    assert!(!matches("%%\n(?s:(?-s:.)) {}\n", "\n"));
+}
+
+#[test]
+fn unknown_flags_are_errors() {
+    assert!(Regex::new("(?x:a)").is_err());
+    assert!(Regex::new("(?i").is_err());
+    assert!(Regex::new("(?:a)").is_ok());
+}
@@ -0,0 +1,94 @@
+use lex::{LexFile, Lexer, Recovery};
+
+#[test]
+fn tokens_have_spans_lines_and_columns() {
+    let lexer = Lexer::from_patterns(&["[a-z]+", "[0-9]+", "[ \\n]+"]).unwrap();
+    let tokens: Vec<_> = lexer.tokens("ab 12\n  cd").map(Result::unwrap).collect();
+
+    let summary: Vec<_> = tokens
+        .iter()
+        .map(|token| (token.text, token.rule, token.span.clone(), token.line, token.column))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("ab", 0, 0..2, 1, 1),
+            (" ", 2, 2..3, 1, 3),
+            ("12", 1, 3..5, 1, 4),
+            ("\n  ", 2, 5..8, 1, 6),
+            ("cd", 0, 8..10, 2, 3),
+        ]
+    );
+}
+
+#[test]
+fn columns_count_chars_not_bytes() {
+    let lexer = Lexer::from_patterns(&["é", "x"]).unwrap();
+    let tokens: Vec<_> = lexer.tokens("éx").map(Result::unwrap).collect();
+
+    assert_eq!(tokens[1].span, 2..3);
+    assert_eq!(tokens[1].column, 2);
+}
+
+#[test]
+fn begin_changes_the_rules_in_use() {
+    let file = LexFile::parse("spec.l", "%x STR\n%%\n[a-z]+ {}\n<STR>[a-z ]+ {}\n").unwrap();
+    let lexer = Lexer::new(&file).unwrap();
+
+    let mut tokens = lexer.tokens("ab cd");
+    assert_eq!(tokens.next().unwrap().unwrap().text, "ab");
+    tokens.begin(1);
+    let token = tokens.next().unwrap().unwrap();
+    assert_eq!((token.text, token.rule), (" cd", 1));
+    assert!(tokens.next().is_none());
+}
+
+#[test]
+fn report_recovery_continues_after_errors() {
+    let lexer = Lexer::from_patterns(&["[a-z]+"]).unwrap().with_recovery(Recovery::Report);
+    let results: Vec<_> = lexer.tokens("a!b").collect();
+
+    assert_eq!(results.len(), 3);
+    let error = results[1].clone().unwrap_err();
+    assert_eq!((error.found, error.offset, error.line, error.column), ('!', 1, 1, 2));
+    assert_eq!(error.to_string(), "1:2: unexpected character '!'");
+    assert_eq!(results[2].clone().unwrap().text, "b");
+}
+
+#[test]
+fn skip_recovery_drops_unmatched_chars() {
+    let lexer = Lexer::from_patterns(&["[a-z]+"]).unwrap().with_recovery(Recovery::Skip);
+    let texts: Vec<_> = lexer.tokens("a!?b").map(|token| token.unwrap().text).collect();
+
+    assert_eq!(texts, vec!["a", "b"]);
+}
+
+#[test]
+fn stop_recovery_ends_at_first_error() {
+    let lexer = Lexer::from_patterns(&["[a-z]+"]).unwrap().with_recovery(Recovery::Stop);
+    let results: Vec<_> = lexer.tokens("a!b").collect();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].clone().unwrap().text, "a");
+    assert_eq!(results[1].clone().unwrap_err().found, '!');
+}
+
+#[test]
+fn patterns_are_compiled_in_utf8_mode() {
+    let lexer = Lexer::from_patterns(&["."]).unwrap();
+    let texts: Vec<_> = lexer.tokens("a€").map(|token| token.unwrap().text).collect();
+
+    assert_eq!(texts, vec!["a", "€"]);
+}
+
+#[test]
+fn variable_trailing_context_is_tracked_per_token() {
+    let lexer = Lexer::from_patterns(&["[a-z]+/[0-9]+", "[a-z0-9]+", "[ ]"]).unwrap();
+    let tokens: Vec<_> = lexer.tokens("ab12 xyz3 q").map(|token| token.unwrap()).collect();
+
+    let summary: Vec<_> = tokens.iter().map(|token| (token.text, token.rule)).collect();
+    assert_eq!(
+        summary,
+        vec![("ab", 0), ("12", 1), (" ", 2), ("xyz", 0), ("3", 1), (" ", 2), ("q", 1)]
+    );
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use common::run_scanner;
+use lex::{Backend, CodeGenerator, LexFile};
+
+const SPEC: &str = r#"%{
+#define SHOW(name) printf("%s %d:%d-%d:%d next %d:%d\n", name, yylloc.first_line, \
+    yylloc.first_column, yylloc.last_line, yylloc.last_column, yylineno, yycolumn)
+%}
+%%
+[a-z]+ { SHOW(yytext); }
+\"[^"]*\" { SHOW("string"); }
+[ \n] {}
+"#;
+
+#[test]
+fn tokens_update_line_column_and_yylloc() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+    let code = CodeGenerator::new(file, dfa, Backend::Switch).code();
+
+    // Columns start at 1 and a newline inside a token moves to the next line
+    let expected = "ab 1:1-1:2 next 1:3\n\
+                    cd 1:4-1:5 next 1:6\n\
+                    string 2:1-3:2 next 3:3\n\
+                    ef 3:4-3:5 next 3:6\n";
+    assert_eq!(run_scanner(&code, "ab cd\n\"x\ny\" ef\n"), expected);
+}
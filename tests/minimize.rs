@@ -0,0 +1,35 @@
+use lex::LexFile;
+
+// Every string over `alphabet` up to `max` chars long
+fn inputs(alphabet: &[char], max: usize) -> Vec<String> {
+    let mut inputs = vec![String::new()];
+    let mut last = vec![String::new()];
+    for _ in 0..max {
+        last = last
+            .iter()
+            .flat_map(|prefix| alphabet.iter().map(move |&c| format!("{}{}", prefix, c)))
+            .collect();
+        inputs.extend(last.iter().cloned());
+    }
+    inputs
+}
+
+#[test]
+fn minimization_merges_states_and_keeps_matches() {
+    let spec = "%%\n(a|b)*abb ABB\nxy|zy XY\n[a-z]+ WORD\n";
+    let dfa = LexFile::parse("spec.l", spec).unwrap().dfa().unwrap();
+    let minimized = dfa.minimize();
+
+    assert!(minimized.states.len() < dfa.states.len());
+    for input in inputs(&['a', 'b', 'x', 'y', 'z'], 5) {
+        assert_eq!(minimized.simulate(&input), dfa.simulate(&input), "{:?}", input);
+    }
+}
+
+#[test]
+fn states_of_different_rules_are_not_merged() {
+    let dfa = LexFile::parse("spec.l", "%%\na A\nb B\n").unwrap().dfa().unwrap().minimize();
+
+    let token = |text: &str, action: &str| (text.to_string(), action.to_string());
+    assert_eq!(dfa.simulate("ab"), vec![token("a", "A"), token("b", "B")]);
+}
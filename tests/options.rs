@@ -0,0 +1,47 @@
+mod common;
+
+use common::walk;
+use lex::{Error, LexFile, LexOptions};
+
+#[test]
+fn option_lines_set_lex_options() {
+    let spec = "%option noyywrap yylineno\n%option prefix=\"calc\" header-file=calc.h stack\n%%\na {}\n";
+    let file = LexFile::parse("spec.l", spec).unwrap();
+
+    let expected = LexOptions {
+        yywrap: false,
+        yylineno: true,
+        prefix: Some("calc".to_string()),
+        header_file: Some("calc.h".to_string()),
+        stack: true,
+        ..LexOptions::default()
+    };
+    assert_eq!(file.options, expected);
+    assert!(file.warnings.is_empty());
+}
+
+#[test]
+fn unknown_options_are_warned_about() {
+    let file = LexFile::parse("spec.l", "%option noyywrap always-interactive\n%%\na {}\n").unwrap();
+
+    assert_eq!(file.warnings.len(), 1);
+    assert_eq!(file.warnings[0].message, "Unknown option 'always-interactive' is ignored");
+    assert_eq!(file.warnings[0].location.column, 18);
+}
+
+#[test]
+fn malformed_options_are_errors() {
+    let error = LexFile::parse("spec.l", "%option prefix\n%%\na {}\n").err().unwrap();
+
+    assert!(matches!(error, Error::Definition { .. }));
+    assert_eq!(error.message(), "Option 'prefix' requires a value");
+}
+
+#[test]
+fn case_insensitive_option_folds_rules() {
+    let file = LexFile::parse("spec.l", "%option case-insensitive\n%%\n\"begin\" {}\n").unwrap();
+    let dfa = file.dfa().unwrap();
+
+    let state = walk(&dfa, dfa.start_state, "BeGiN".chars());
+    assert_eq!(state.and_then(|state| dfa.rule_index(state)), Some(0));
+}
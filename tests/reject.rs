@@ -0,0 +1,65 @@
+mod common;
+
+use common::{run_scanner, walk};
+use lex::{Backend, CodeGenerator, LexFile, DFA};
+
+const SPEC: &str = "%%\nfrob { REJECT; }\nfr[a-z]* {}\nfro {}\n";
+
+fn state_after(file: &LexFile, input: &str) -> (DFA, usize) {
+    let dfa = file.dfa().unwrap().minimize();
+    let state = walk(&dfa, dfa.start_state, input.chars()).unwrap();
+    (dfa, state)
+}
+
+#[test]
+fn reject_falls_back_to_rules_matching_the_same_text() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+
+    let (dfa, state) = state_after(&file, "frob");
+    assert_eq!(dfa.rule_index(state), Some(0));
+    assert_eq!(dfa.fallback_rules[&state], vec![(1, None)]);
+
+    let (dfa, state) = state_after(&file, "fro");
+    assert_eq!(dfa.rule_index(state), Some(1));
+    assert_eq!(dfa.fallback_rules[&state], vec![(2, None)]);
+
+    // Reachable through REJECT, so not reported
+    assert!(file.unmatchable_rules(&dfa).is_empty());
+}
+
+#[test]
+fn fallbacks_are_generated_after_the_accepted_rule() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+    let (dfa, state) = state_after(&file, "frob");
+    let code = CodeGenerator::new(file, dfa, Backend::Switch).code();
+
+    // Same length, lower priority than rule 0
+    assert!(code.contains(&format!("    case {}:\n        yy_push_match(state, 1, 2, length, length);\n", state)));
+    assert!(code.contains("    yy_add_fallback_matches(state, length);\n"));
+}
+
+#[test]
+fn generated_scanner_runs_the_fallback_action() {
+    let spec = r#"%%
+frob { printf("0:%s\n", yytext); REJECT; }
+fr[a-z]* { printf("1:%s\n", yytext); }
+fro { printf("2:%s\n", yytext); }
+\n {}
+"#;
+    let file = LexFile::parse("spec.l", spec).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+    let code = CodeGenerator::new(file, dfa, Backend::Switch).code();
+
+    assert_eq!(run_scanner(&code, "frob
+fro
+"), "0:frob\n1:frob\n1:fro\n");
+}
+
+#[test]
+fn no_fallbacks_without_reject() {
+    let file = LexFile::parse("spec.l", "%%\nfrob {}\nfr[a-z]* {}\nfro {}\n").unwrap();
+    let dfa = file.dfa().unwrap();
+
+    assert!(dfa.fallback_rules.is_empty());
+    assert_eq!(file.unmatchable_rules(&dfa), vec![2]);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::walk;
+use lex::{LexFile, Lexer, Rule, DFA, NFA};
+
+fn rule_of(file: &LexFile, pattern: &str) -> usize {
+    file.rules
+        .iter()
+        .position(|rule| rule.pattern == pattern)
+        .unwrap()
+}
+
+#[test]
+fn keywords_win_over_identifiers() {
+    let file = LexFile::new("examples/c.l").unwrap();
+    let lexer = Lexer::new(&file).unwrap();
+
+    let id = rule_of(&file, "[a-zA-Z][a-zA-Z0-9_]*");
+    let expected = [
+        ("while", rule_of(&file, "\"while\"")),
+        ("int", rule_of(&file, "\"int\"")),
+        ("return", rule_of(&file, "\"return\"")),
+        ("whilex", id),
+        ("in", id),
+        ("foo", id),
+    ];
+
+    let words: Vec<&str> = expected.iter().map(|(text, _)| *text).collect();
+    let input = words.join(" ");
+    let tokens: Vec<(&str, usize)> = lexer
+        .tokens(&input)
+        .map(Result::unwrap)
+        .filter(|token| token.text != " ")
+        .map(|token| (token.text, token.rule))
+        .collect();
+
+    assert_eq!(tokens, expected);
+}
+
+#[test]
+fn minimized_dfa_keeps_rule_of_keyword_states() {
+    let file = LexFile::new("examples/c.l").unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+
+    let state = walk(&dfa, dfa.start_state, "if".chars());
+
+    assert_eq!(dfa.rule_index(state.unwrap()), Some(rule_of(&file, "\"if\"")));
+}
+
+#[test]
+fn rule_index_decides_over_state_id() {
+    let keyword = NFA::new("if").unwrap();
+    let identifier = NFA::new("[a-z]+").unwrap();
+    let (mut nfa, start_states) = NFA::with_start_states(1, vec![(keyword, vec![0]), (identifier, vec![0])]);
+
+    // The keyword states have the lower IDs, but now belong to the later rule
+    for rule in nfa.rules.values_mut() {
+        *rule = 1 - *rule;
+    }
+
+    let dfa = DFA::from_start_states(&nfa, &start_states);
+    let state = walk(&dfa, dfa.start_state, "if".chars());
+
+    assert_eq!(dfa.rule_index(state.unwrap()), Some(0));
+}
+
+#[test]
+fn shadowed_rules_are_unmatchable() {
+    let rules = ["[a-z]+", "\"while\"", "[0-9]+", "\"if\"|\"9x\""]
+        .iter()
+        .map(|pattern| Rule::new(pattern.to_string(), String::new()).unwrap())
+        .collect();
+    let file = LexFile::from_rules(rules);
+    let dfa = file.dfa().unwrap().minimize();
+
+    assert_eq!(file.unmatchable_rules(&dfa), vec![1]);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::run_rust_scanner;
+use lex::{Error, LexFile, RustGenerator};
+
+const MAIN: &str = r#"
+fn main() {
+    let mut input = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut input).unwrap();
+    for token in Lexer::new(&input) {
+        match token {
+            Ok(token) => println!("{} {:?} {}:{}", token.rule, token.as_str(), token.line, token.column),
+            Err(error) => println!("{}", error),
+        }
+    }
+}
+"#;
+
+// Rules compiled in UTF-8 mode, as `--language=rust` does
+fn generate(rules: &str) -> Result<String, Error> {
+    let mut file = LexFile::parse("spec.l", &format!("%%\n{}%%\n{}", rules, MAIN))?;
+    file.options.utf8 = true;
+    file.compile_rules()?;
+    let dfa = file.dfa()?.minimize();
+    Ok(RustGenerator::new(file, dfa)?.code())
+}
+
+#[test]
+fn generated_lexer_reports_the_rule_of_each_token() {
+    let code = generate("\"if\" {}\n[a-zé]+ {}\n[0-9]+ {}\n[ \\n] { continue; }\n").unwrap();
+
+    assert_eq!(
+        run_rust_scanner(&code, "if été 42\nx?"),
+        "0 \"if\" 1:1\n1 \"été\" 1:4\n2 \"42\" 1:8\n1 \"x\" 2:1\n2:2: unexpected byte 0x3F\n"
+    );
+}
+
+#[test]
+fn rules_sharing_an_action_keep_their_own_index() {
+    let code = generate("[a-z]+ |\n[0-9]+ {}\n[ ] { continue; }\n").unwrap();
+
+    assert_eq!(run_rust_scanner(&code, "ab 12"), "0 \"ab\" 1:1\n1 \"12\" 1:4\n");
+}
+
+#[test]
+fn reject_is_an_error() {
+    let error = generate("[a-z]+ {}\nfrob { REJECT; }\n").err().unwrap();
+
+    assert!(matches!(error, Error::Rule { .. }));
+    assert_eq!(error.to_string(), "spec.l:3:8: REJECT is not supported when generating Rust");
+}
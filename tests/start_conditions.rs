@@ -0,0 +1,54 @@
+mod common;
+
+use common::walk;
+use lex::{Backend, CodeGenerator, LexFile, DFA};
+
+const SPEC: &str = "%s INC\n%x EXC\n%%\na A\n<INC>b B\n<EXC>c C\n<*>d D\n";
+
+fn condition(file: &LexFile, name: &str) -> usize {
+    file.start_conditions.iter().position(|condition| condition.name == name).unwrap()
+}
+
+// Action of the rule matching `input` in `condition`, if any
+fn action(dfa: &DFA, condition: usize, input: &str) -> Option<String> {
+    let state = walk(dfa, dfa.initial_state(condition, true), input.chars())?;
+    dfa.actions.get(&state).cloned()
+}
+
+// Actions matching each of "a", "b", "c" and "d" in `condition`
+fn actions(dfa: &DFA, condition: usize) -> Vec<Option<String>> {
+    ["a", "b", "c", "d"].iter().map(|input| action(dfa, condition, input)).collect()
+}
+
+fn some(action: &str) -> Option<String> {
+    Some(action.to_string())
+}
+
+#[test]
+fn inclusive_conditions_keep_unprefixed_rules() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+
+    assert_eq!(actions(&dfa, 0), vec![some("A"), None, None, some("D")]);
+    assert_eq!(actions(&dfa, condition(&file, "INC")), vec![some("A"), some("B"), None, some("D")]);
+}
+
+#[test]
+fn exclusive_conditions_only_use_their_rules_and_star() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+
+    assert_eq!(actions(&dfa, condition(&file, "EXC")), vec![None, None, some("C"), some("D")]);
+}
+
+#[test]
+fn generated_begin_selects_condition_start_states() {
+    let file = LexFile::parse("spec.l", SPEC).unwrap();
+    let dfa = file.dfa().unwrap().minimize();
+    let code = CodeGenerator::new(file, dfa, Backend::Switch).code();
+
+    assert!(code.contains("#define INC 1\n"));
+    assert!(code.contains("#define EXC 2\n"));
+    assert!(code.contains("#define BEGIN yy_start = \n"));
+    assert!(code.contains("yy_start_states[yy_start * 2 + yy_at_bol]"));
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::run_scanner;
+use lex::{full_table, Backend, CodeGenerator, CompressedTables, LexFile};
+
+const C_SPEC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/c.l");
+
+#[test]
+fn tables_agree_with_the_dfa() {
+    let dfa = LexFile::new(C_SPEC).unwrap().dfa().unwrap().minimize();
+    let compressed = CompressedTables::new(&dfa);
+    let full = full_table(&dfa);
+
+    for &state in &dfa.states {
+        assert_eq!(full[state].len(), dfa.class_count());
+        for (class, &entry) in full[state].iter().enumerate() {
+            let next = dfa.transitions.get(&(state, class)).copied();
+            assert_eq!(compressed.transition(state, class), next, "state {} on class {}", state, class);
+            assert_eq!(entry, next.map_or(-1, |next| next as i32));
+        }
+    }
+}
+
+#[test]
+fn backends_give_the_same_tokens() {
+    let spec = r#"%%
+if { printf("IF\n"); }
+[a-z]+ { printf("ID %s\n", yytext); }
+[0-9]+ { printf("NUM %s\n", yytext); }
+[ \n] {}
+"#;
+    let input = "if iffy 42 x1\n";
+    let expected = "IF\nID iffy\nNUM 42\nID x\nNUM 1\n";
+
+    for backend in [Backend::Switch, Backend::Compressed, Backend::Full] {
+        let file = LexFile::parse("spec.l", spec).unwrap();
+        let dfa = file.dfa().unwrap().minimize();
+        assert_eq!(run_scanner(&CodeGenerator::new(file, dfa, backend).code(), input), expected);
+    }
+}
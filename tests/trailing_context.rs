@@ -0,0 +1,29 @@
+mod common;
+
+use common::tokens;
+use lex::{HeadLength, Rule};
+
+fn head_length(pattern: &str) -> HeadLength {
+    let rule = Rule::new(pattern.to_string(), String::new()).unwrap();
+    *rule.nfa.head_lengths.values().next().unwrap()
+}
+
+#[test]
+fn fixed_head() {
+    assert_eq!(head_length("ab/c"), HeadLength::Fixed(2));
+    assert_eq!(tokens(&["ab/c", "[a-z]"], "abcab"), r#""ab":0 "c":1 "a":1 "b":1"#);
+}
+
+#[test]
+fn fixed_tail() {
+    assert_eq!(head_length("a+/bc"), HeadLength::FromTail(2));
+    assert_eq!(tokens(&["a+/bc", "[a-z]+"], "aaabc"), r#""aaa":0 "bc":1"#);
+}
+
+#[test]
+fn variable_head_and_tail() {
+    assert!(matches!(head_length("a+/b+"), HeadLength::Marker(_)));
+    assert_eq!(tokens(&["a+/b+", "[a-z]"], "aabbb"), r#""aa":0 "b":1 "b":1 "b":1"#);
+    // Without the trailing context the head does not match
+    assert_eq!(tokens(&["a+/b+", "[a-z]"], "aa"), r#""a":1 "a":1"#);
+}
@@ -0,0 +1,52 @@
+use lex::{utf8_sequences, HeadLength, LexFile, Lexer};
+
+fn matches(sequences: &[Vec<(u8, u8)>], bytes: &[u8]) -> bool {
+    sequences.iter().any(|sequence| {
+        sequence.len() == bytes.len() && sequence.iter().zip(bytes).all(|(&(lo, hi), &byte)| lo <= byte && byte <= hi)
+    })
+}
+
+#[test]
+fn sequences_match_exactly_the_encoded_range() {
+    let sequences = utf8_sequences('\u{7F}', '\u{10000}');
+
+    let boundaries = ['\u{7E}', '\u{7F}', '\u{80}', 'é', '\u{7FF}', '\u{800}', '\u{D7FF}', '\u{E000}'];
+    for c in boundaries.into_iter().chain(['\u{FFFF}', '\u{10000}', '\u{10001}']) {
+        let mut buffer = [0; 4];
+        let bytes = c.encode_utf8(&mut buffer).as_bytes();
+        let expected = ('\u{7F}'..='\u{10000}').contains(&c);
+        assert_eq!(matches(&sequences, bytes), expected, "{:?}", c);
+    }
+}
+
+#[test]
+fn dot_and_negated_classes_match_whole_code_points() {
+    let file = LexFile::parse("spec.l", "%option utf8\n%%\n[α-ω]+ {}\n[^a-z] {}\n. {}\n").unwrap();
+    let lexer = Lexer::new(&file).unwrap();
+
+    let tokens: Vec<(usize, &str)> = lexer
+        .tokens("αβé日x")
+        .map(|token| token.map(|token| (token.rule, token.text)).unwrap())
+        .collect();
+    assert_eq!(tokens, vec![(0, "αβ"), (1, "é"), (1, "日"), (2, "x")]);
+}
+
+#[test]
+fn trailing_context_lengths_count_bytes() {
+    let spec = "%option utf8\n%%\né/x {}\nαβ+/γ {}\n[α-ω]+/[0-9]+ {}\n. {}\n";
+    let file = LexFile::parse("spec.l", spec).unwrap();
+    let head_length = |rule: usize| *file.rules[rule].nfa.head_lengths.values().next().unwrap();
+    assert_eq!(head_length(0), HeadLength::Fixed(2));
+    assert_eq!(head_length(1), HeadLength::FromTail(2));
+    assert!(matches!(head_length(2), HeadLength::Marker(_)));
+
+    let lexer = Lexer::new(&file).unwrap();
+    let tokens: Vec<(usize, &str)> = lexer
+        .tokens("éxαββγ δε12")
+        .map(|token| token.map(|token| (token.rule, token.text)).unwrap())
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![(0, "é"), (3, "x"), (1, "αββ"), (3, "γ"), (3, " "), (2, "δε"), (3, "1"), (3, "2")]
+    );
+}